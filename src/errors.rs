@@ -0,0 +1,216 @@
+use crate::landscape::command::CallSite;
+use std::error::Error;
+use std::fmt;
+
+/// Collection of problems found while resolving a bonsai landscape.
+///
+/// Resolution keeps going after most problems so that all of them can be reported together
+/// instead of one at a time.
+#[derive(Debug, Default)]
+pub struct BonsaiErrors {
+    pub errors: Vec<Box<dyn Error>>,
+}
+
+impl BonsaiErrors {
+    pub fn push(&mut self, error: Box<dyn Error>) {
+        self.errors.push(error);
+    }
+
+    pub fn extend(&mut self, other: BonsaiErrors) {
+        self.errors.extend(other.errors);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Convert the collected problems into a result, succeeding only if nothing was found.
+    pub fn into_result<T>(self, value: T) -> Result<T, Box<dyn Error>> {
+        if self.is_empty() {
+            Ok(value)
+        } else {
+            Err(Box::new(self))
+        }
+    }
+}
+
+impl fmt::Display for BonsaiErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.errors.len() == 1 {
+            return write!(f, "{}", self.errors[0]);
+        }
+
+        write!(f, "Found {} problems:", self.errors.len())?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for BonsaiErrors {}
+
+/// Problem with a `bonsai:` call made in a landscape.
+#[derive(Debug)]
+pub enum BonsaiCallError {
+    /// The call is not of the form `pot:function`.
+    Malformed { call: String, call_site: CallSite },
+    /// The called pot is not provided by any source.
+    UnknownPot {
+        pot: String,
+        function: String,
+        call_site: CallSite,
+        suggestions: Vec<String>,
+    },
+    /// The called pot exists, but does not define the called function.
+    UnknownFunction {
+        pot: String,
+        function: String,
+        call_site: CallSite,
+        suggestions: Vec<String>,
+    },
+    /// No pot defines the task template a task is created from.
+    UnknownTask {
+        task: String,
+        call_site: CallSite,
+        suggestions: Vec<String>,
+    },
+}
+
+fn write_suggestions(f: &mut fmt::Formatter, suggestions: &[String]) -> fmt::Result {
+    if !suggestions.is_empty() {
+        let quoted: Vec<String> = suggestions.iter().map(|s| format!("'{}'", s)).collect();
+        write!(f, ", did you mean {}?", quoted.join(" or "))?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for BonsaiCallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BonsaiCallError::Malformed { call, call_site } => write!(
+                f,
+                "Malformed bonsai call '{}' in {}, expected 'pot:function'",
+                call, call_site
+            ),
+            BonsaiCallError::UnknownPot {
+                pot,
+                function,
+                call_site,
+                suggestions,
+            } => {
+                write!(
+                    f,
+                    "Unknown pot '{}' in call to '{}:{}' in {}",
+                    pot, pot, function, call_site
+                )?;
+                write_suggestions(f, suggestions)
+            }
+            BonsaiCallError::UnknownFunction {
+                pot,
+                function,
+                call_site,
+                suggestions,
+            } => {
+                write!(
+                    f,
+                    "Unknown function '{}' in pot '{}' in {}",
+                    function, pot, call_site
+                )?;
+                write_suggestions(f, suggestions)
+            }
+            BonsaiCallError::UnknownTask {
+                task,
+                call_site,
+                suggestions,
+            } => {
+                write!(f, "Unknown pot task '{}' in {}", task, call_site)?;
+                write_suggestions(f, suggestions)
+            }
+        }
+    }
+}
+
+impl Error for BonsaiCallError {}
+
+/// A chain of pot functions that call each other in a loop.
+#[derive(Debug)]
+pub struct CyclicDependencyError {
+    /// Functions in the cycle as `pot:function`, starting and ending with the same function.
+    pub chain: Vec<String>,
+}
+
+impl fmt::Display for CyclicDependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Cyclic dependency between pot functions: {}",
+            self.chain.join(" -> ")
+        )
+    }
+}
+
+impl Error for CyclicDependencyError {}
+
+/// Problem with the parameters given to a pot function in a `bonsai:` call.
+#[derive(Debug)]
+pub enum BonsaiParamError {
+    /// A required parameter was not given.
+    Missing {
+        call: String,
+        param: String,
+        call_site: CallSite,
+    },
+    /// A parameter that the function does not declare was given.
+    Unknown {
+        call: String,
+        param: String,
+        call_site: CallSite,
+    },
+    /// The value given for a parameter is not one the parameter accepts.
+    Invalid {
+        call: String,
+        param: String,
+        value: String,
+        expected: String,
+        call_site: CallSite,
+    },
+}
+
+impl fmt::Display for BonsaiParamError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BonsaiParamError::Missing {
+                call,
+                param,
+                call_site,
+            } => write!(
+                f,
+                "Missing required parameter '{}' in call to '{}' in {}",
+                param, call, call_site
+            ),
+            BonsaiParamError::Unknown {
+                call,
+                param,
+                call_site,
+            } => write!(
+                f,
+                "Unknown parameter '{}' in call to '{}' in {}",
+                param, call, call_site
+            ),
+            BonsaiParamError::Invalid {
+                call,
+                param,
+                value,
+                expected,
+                call_site,
+            } => write!(
+                f,
+                "Invalid value '{}' for parameter '{}' in call to '{}' in {}, expected {}",
+                value, param, call, call_site, expected
+            ),
+        }
+    }
+}
+
+impl Error for BonsaiParamError {}
@@ -1,33 +1,92 @@
+use crate::errors::{BonsaiCallError, BonsaiErrors};
+use crate::landscape::param::{check_call_params, with_defaults};
+use crate::landscape::pot::{BonsaiPotParam, BonsaiPotTask};
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 use shrub_rs::models::commands::FunctionCall;
 use shrub_rs::models::{commands::EvgCommand, params::ParamValue};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// Maximum number of similarly named functions to suggest for an unknown call.
+const MAX_SUGGESTIONS: usize = 3;
+/// Number of characters of the params hash used in the name of a specialized function.
+const SPECIALIZED_HASH_LENGTH: usize = 8;
+
+/// Get a param value as YAML with the keys of maps sorted, so equal values look the same.
+fn canonical_value(value: &ParamValue) -> Value {
+    let value = match value {
+        ParamValue::Map(map) => serde_yaml::to_value(map.iter().collect::<BTreeMap<_, _>>()),
+        _ => serde_yaml::to_value(value),
+    };
+    value.unwrap_or(Value::Null)
+}
+
+/// 64-bit FNV-1a hash, which unlike the hasher of the standard library is stable across releases.
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BonsaiCall {
     pub bonsai: String,
     pub params: Option<HashMap<String, ParamValue>>,
+    /// Substitute the params into a specialized copy of the function instead of passing them
+    /// as expansions, overriding the setting of the pot.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub substitute_params: Option<bool>,
 }
 
 impl BonsaiCall {
+    /// Split the call into the name of the pot and the name of the function being called.
+    pub fn parse(&self) -> Option<(&str, &str)> {
+        self.bonsai
+            .split_once(':')
+            .filter(|(pot, function)| !pot.is_empty() && !function.is_empty())
+    }
+
     pub fn get_fn_name(&self) -> String {
-        let parts: Vec<&str> = self.bonsai.split(':').collect();
-        format!("{}_{}", parts[0], parts[1])
+        match self.parse() {
+            Some((pot, function)) => format!("{}_{}", pot, function),
+            None => self.bonsai.clone(),
+        }
     }
 
     pub fn get_pot_name(&self) -> String {
-        let parts: Vec<&str> = self.bonsai.split(':').collect();
-        parts[0].to_string()
+        match self.parse() {
+            Some((pot, _)) => pot.to_string(),
+            None => self.bonsai.clone(),
+        }
+    }
+
+    /// Get the name of the function specialized for the params of this call.
+    ///
+    /// The name ends with a hash of the params, so calls with the same params share a function.
+    pub fn get_specialized_fn_name(&self) -> String {
+        let params: BTreeMap<&String, Value> = self
+            .params
+            .iter()
+            .flatten()
+            .map(|(name, value)| (name, canonical_value(value)))
+            .collect();
+        let contents = serde_yaml::to_string(&params).unwrap_or_default();
+        let hash = format!("{:016x}", fnv1a_hash(contents.as_bytes()));
+        format!(
+            "{}_{}",
+            self.get_fn_name(),
+            &hash[..SPECIALIZED_HASH_LENGTH]
+        )
     }
 
     pub fn to_evg_command(&self) -> EvgCommand {
-        let parameters = match &self.params {
-            Some(vars) => Some(vars.clone()),
-            None => None,
-        };
         EvgCommand::Function(FunctionCall {
             func: self.get_fn_name(),
-            vars: parameters,
+            vars: self.params.clone(),
             timeout_secs: None,
         })
     }
@@ -35,27 +94,217 @@ impl BonsaiCall {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum BonsaiCommand {
     EvergreenNative(EvgCommand),
     Bonsai(BonsaiCall),
 }
 
+/// Location in a landscape that a bonsai call is made from.
+#[derive(Debug, Clone)]
+pub enum CallSite {
+    Task(String),
+    Function(String),
+    Pre,
+    Post,
+    Timeout,
+    /// A function defined in a pot, given as `pot:function`.
+    PotFunction(String),
+    /// A task template defined in a pot, given as `pot:task`.
+    PotTask(String),
+}
+
+impl fmt::Display for CallSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CallSite::Task(name) => write!(f, "task '{}'", name),
+            CallSite::Function(name) => write!(f, "function '{}'", name),
+            CallSite::Pre => write!(f, "pre"),
+            CallSite::Post => write!(f, "post"),
+            CallSite::Timeout => write!(f, "timeout"),
+            CallSite::PotFunction(name) => write!(f, "pot function '{}'", name),
+            CallSite::PotTask(name) => write!(f, "pot task '{}'", name),
+        }
+    }
+}
+
+/// Find the names most similar to the given name, closest first.
+fn closest_matches<'a>(name: &str, candidates: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let max_distance = std::cmp::max(2, name.len() / 3);
+    let mut matches: Vec<(usize, &String)> = candidates
+        .map(|c| (strsim::levenshtein(name, c), c))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    matches.sort();
+    matches
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| c.clone())
+        .collect()
+}
+
+/// Check that the given bonsai call refers to one of the known functions (`pot:function`).
+pub fn find_call_error(
+    bonsai_call: &BonsaiCall,
+    call_site: &CallSite,
+    known_functions: &HashSet<String>,
+) -> Option<BonsaiCallError> {
+    let (pot, function) = match bonsai_call.parse() {
+        Some(parts) => parts,
+        None => {
+            return Some(BonsaiCallError::Malformed {
+                call: bonsai_call.bonsai.clone(),
+                call_site: call_site.clone(),
+            })
+        }
+    };
+
+    let call_name = format!("{}:{}", pot, function);
+    if known_functions.contains(&call_name) {
+        return None;
+    }
+
+    let pot_prefix = format!("{}:", pot);
+    if known_functions.iter().any(|f| f.starts_with(&pot_prefix)) {
+        Some(BonsaiCallError::UnknownFunction {
+            pot: pot.to_string(),
+            function: function.to_string(),
+            call_site: call_site.clone(),
+            suggestions: closest_matches(&call_name, known_functions.iter()),
+        })
+    } else {
+        let known_pots: HashSet<String> = known_functions
+            .iter()
+            .filter_map(|f| f.split_once(':').map(|(p, _)| p.to_string()))
+            .collect();
+        Some(BonsaiCallError::UnknownPot {
+            pot: pot.to_string(),
+            function: function.to_string(),
+            call_site: call_site.clone(),
+            suggestions: closest_matches(pot, known_pots.iter()),
+        })
+    }
+}
+
+#[derive(Default)]
 pub struct BonsaiTranslator {
+    /// Names of the pots whose commands are used, so their support files are needed.
     pub seen_pots: HashSet<String>,
+    /// Evergreen names of the pot functions that have been called.
+    pub seen_functions: HashSet<String>,
+    /// Names of all pot functions that calls can be resolved against, as `pot:function`.
+    pub known_functions: HashSet<String>,
+    /// Parameters declared by pot functions, keyed by `pot:function`.
+    pub function_params: HashMap<String, Vec<BonsaiPotParam>>,
+    /// Pots whose commands are inlined into each pot function, keyed by `pot:function`.
+    pub inlined_pots: HashMap<String, HashSet<String>>,
+    /// Pots whose functions have params substituted into them by default.
+    pub substituted_pots: HashSet<String>,
+    /// Task templates defined by pots, keyed by `pot:task`.
+    pub task_templates: HashMap<String, BonsaiPotTask>,
+    /// Calls made to specialized functions, keyed by the name of the specialized function.
+    pub specialized_calls: HashMap<String, BonsaiCall>,
+    /// Problems found while translating.
+    pub errors: BonsaiErrors,
+    /// Path support files are copied to, relative to the working directory of a task.
+    pub support_files_path: String,
 }
 
 impl BonsaiTranslator {
     pub fn new() -> Self {
-        Self {
-            seen_pots: HashSet::new(),
+        Self::default()
+    }
+
+    /// Resolve references to support files in pot functions relative to the given path.
+    pub fn with_support_files_path(mut self, support_files_path: &str) -> Self {
+        self.support_files_path = support_files_path.to_string();
+        self
+    }
+
+    /// Report the given bonsai call if it cannot be resolved or its parameters are not valid.
+    pub fn check_call(&mut self, bonsai_call: &BonsaiCall, call_site: &CallSite) {
+        if let Some(error) = find_call_error(bonsai_call, call_site, &self.known_functions) {
+            self.errors.push(Box::new(error));
+        } else if let Some(params) = self.function_params.get(&bonsai_call.bonsai) {
+            for error in check_call_params(bonsai_call, call_site, params) {
+                self.errors.push(Box::new(error));
+            }
+        }
+    }
+
+    /// Find the task template a `bonsai_task` refers to, reporting it if it cannot be found or
+    /// the params given to it are not valid.
+    pub fn find_task_template(
+        &mut self,
+        task_call: &BonsaiCall,
+        call_site: &CallSite,
+    ) -> Option<BonsaiPotTask> {
+        self.seen_pots.insert(task_call.get_pot_name());
+        let template = match self.task_templates.get(&task_call.bonsai) {
+            Some(template) => template.clone(),
+            None => {
+                self.errors.push(Box::new(BonsaiCallError::UnknownTask {
+                    task: task_call.bonsai.clone(),
+                    call_site: call_site.clone(),
+                    suggestions: closest_matches(&task_call.bonsai, self.task_templates.keys()),
+                }));
+                return None;
+            }
+        };
+
+        if let Some(params) = &template.params {
+            for error in check_call_params(task_call, call_site, params) {
+                self.errors.push(Box::new(error));
+            }
+        }
+        Some(template)
+    }
+
+    /// Record the pots whose commands are used by the given call.
+    pub fn record_pots(&mut self, bonsai_call: &BonsaiCall) {
+        self.seen_pots.insert(bonsai_call.get_pot_name());
+        if let Some(pots) = self.inlined_pots.get(&bonsai_call.bonsai) {
+            self.seen_pots.extend(pots.iter().cloned());
+        }
+    }
+
+    /// Get the given call with defaults filled in for the parameters it does not give.
+    pub fn with_defaults(&self, bonsai_call: &BonsaiCall) -> BonsaiCall {
+        match self.function_params.get(&bonsai_call.bonsai) {
+            Some(params) => with_defaults(bonsai_call, params),
+            None => bonsai_call.clone(),
         }
     }
 
-    pub fn translate_command(&mut self, bonsai_command: &BonsaiCommand) -> EvgCommand {
+    /// Check if the params of the given call should be substituted into a specialized function.
+    fn is_substituted(&self, bonsai_call: &BonsaiCall) -> bool {
+        bonsai_call
+            .substitute_params
+            .unwrap_or_else(|| self.substituted_pots.contains(&bonsai_call.get_pot_name()))
+    }
+
+    pub fn translate_command(
+        &mut self,
+        bonsai_command: &BonsaiCommand,
+        call_site: &CallSite,
+    ) -> EvgCommand {
         match bonsai_command {
             BonsaiCommand::Bonsai(b_cmd) => {
-                self.seen_pots.insert(b_cmd.get_pot_name());
-                b_cmd.to_evg_command()
+                self.record_pots(b_cmd);
+                self.check_call(b_cmd, call_site);
+                let call = self.with_defaults(b_cmd);
+                if self.is_substituted(&call) {
+                    let fn_name = call.get_specialized_fn_name();
+                    self.specialized_calls.insert(fn_name.clone(), call);
+                    EvgCommand::Function(FunctionCall {
+                        func: fn_name,
+                        vars: None,
+                        timeout_secs: None,
+                    })
+                } else {
+                    self.seen_functions.insert(call.get_fn_name());
+                    call.to_evg_command()
+                }
             }
             BonsaiCommand::EvergreenNative(c) => c.clone(),
         }
@@ -64,16 +313,80 @@ impl BonsaiTranslator {
     pub fn translate_command_list(
         &mut self,
         bonsai_command_list: &[BonsaiCommand],
+        call_site: &CallSite,
     ) -> Vec<EvgCommand> {
         bonsai_command_list
             .iter()
-            .map(|c| self.translate_command(c))
+            .map(|c| self.translate_command(c, call_site))
             .collect()
     }
 
     pub fn is_used(&self, fn_name: &str) -> bool {
-        self.seen_pots
-            .iter()
-            .any(|pot_name| fn_name.starts_with(pot_name))
+        self.seen_functions.contains(fn_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(params: Vec<(&str, ParamValue)>) -> BonsaiCall {
+        BonsaiCall {
+            bonsai: "pot:function".to_string(),
+            params: Some(
+                params
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+            ),
+            substitute_params: None,
+        }
+    }
+
+    fn map(entries: &[(&str, &str)]) -> ParamValue {
+        ParamValue::Map(
+            entries
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_specialized_fn_name_is_stable() {
+        let first = call(vec![
+            ("a", ParamValue::from("x")),
+            ("b", map(&[("k", "v"), ("l", "w")])),
+        ]);
+        let second = call(vec![
+            ("b", map(&[("l", "w"), ("k", "v")])),
+            ("a", ParamValue::from("x")),
+        ]);
+
+        let name = first.get_specialized_fn_name();
+        assert!(name.starts_with("pot_function_"));
+        assert_eq!(name.len(), "pot_function_".len() + SPECIALIZED_HASH_LENGTH);
+        assert_eq!(name, second.get_specialized_fn_name());
+    }
+
+    #[test]
+    fn test_specialized_fn_name_differs_for_different_params() {
+        let names: HashSet<String> = [
+            call(vec![("a", map(&[("k", "v")]))]),
+            call(vec![("a", map(&[("k", "w")]))]),
+            call(vec![("a", ParamValue::List(vec!["x, y".to_string()]))]),
+            call(vec![(
+                "a",
+                ParamValue::List(vec!["x".to_string(), "y".to_string()]),
+            )]),
+            call(vec![("a", ParamValue::from("x, y"))]),
+            call(vec![("a", ParamValue::Number(1))]),
+            call(vec![]),
+        ]
+        .iter()
+        .map(|c| c.get_specialized_fn_name())
+        .collect();
+
+        assert_eq!(names.len(), 7);
     }
 }
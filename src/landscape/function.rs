@@ -1,13 +1,15 @@
 use crate::landscape::command::BonsaiCommand;
+use crate::landscape::param::inline_call;
 use serde::{Deserialize, Serialize};
 use shrub_rs::models::commands::EvgCommand;
 use shrub_rs::models::project::FunctionDefinition;
 use std::collections::HashMap;
 
-use super::command::BonsaiTranslator;
+use super::command::{BonsaiTranslator, CallSite};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum BonsaiFunctionDef {
     SingleCommand(BonsaiCommand),
     CommandList(Vec<BonsaiCommand>),
@@ -16,15 +18,22 @@ pub enum BonsaiFunctionDef {
 fn cmd_list_to_function_def(
     cmd_list: &[BonsaiCommand],
     bonsai_cmds: &HashMap<String, FunctionDefinition>,
+    bonsai_translator: &mut BonsaiTranslator,
+    call_site: &CallSite,
 ) -> Vec<EvgCommand> {
     let mut evg_cmd_list = vec![];
     cmd_list.iter().for_each(|bc| match bc {
         BonsaiCommand::EvergreenNative(cmd) => evg_cmd_list.push(cmd.clone()),
         BonsaiCommand::Bonsai(cmd) => {
-            let commands = bonsai_cmds.get(&cmd.get_fn_name()).unwrap();
-            match commands {
-                FunctionDefinition::SingleCommand(evg_cmd) => evg_cmd_list.push(evg_cmd.clone()),
-                FunctionDefinition::CommandList(cmds) => evg_cmd_list.extend(cmds.to_vec()),
+            bonsai_translator.record_pots(cmd);
+            bonsai_translator.check_call(cmd, call_site);
+            if let Some(fn_def) = bonsai_cmds.get(&cmd.get_fn_name()) {
+                match inline_call(&bonsai_translator.with_defaults(cmd), fn_def) {
+                    Ok(cmds) => evg_cmd_list.extend(cmds),
+                    Err(err) => bonsai_translator
+                        .errors
+                        .push(format!("{} in {}", err, call_site).into()),
+                }
             }
         }
     });
@@ -33,17 +42,19 @@ fn cmd_list_to_function_def(
 }
 
 pub fn translate_fn_def(
+    fn_name: &str,
     fn_def: &BonsaiFunctionDef,
     bonsai_fns: &HashMap<String, FunctionDefinition>,
     bonsai_translator: &mut BonsaiTranslator,
 ) -> FunctionDefinition {
+    let call_site = CallSite::Function(fn_name.to_string());
     match fn_def {
         BonsaiFunctionDef::SingleCommand(cmd) => {
-            FunctionDefinition::SingleCommand(bonsai_translator.translate_command(cmd))
-        }
-        BonsaiFunctionDef::CommandList(cmd_list) => {
-            FunctionDefinition::CommandList(cmd_list_to_function_def(cmd_list, bonsai_fns))
+            FunctionDefinition::SingleCommand(bonsai_translator.translate_command(cmd, &call_site))
         }
+        BonsaiFunctionDef::CommandList(cmd_list) => FunctionDefinition::CommandList(
+            cmd_list_to_function_def(cmd_list, bonsai_fns, bonsai_translator, &call_site),
+        ),
     }
 }
 
@@ -56,7 +67,7 @@ pub fn translate_functions(
     for (k, v) in fn_map {
         new_map.insert(
             k.clone(),
-            translate_fn_def(v, bonsai_fns, bonsai_translator),
+            translate_fn_def(k, v, bonsai_fns, bonsai_translator),
         );
     }
     new_map
@@ -0,0 +1,243 @@
+use crate::errors::{BonsaiErrors, CyclicDependencyError};
+use crate::landscape::command::{find_call_error, BonsaiCommand, CallSite};
+use crate::landscape::param::check_call_params;
+use crate::landscape::pot::{BonsaiPot, BonsaiPotFunction, BonsaiPotParam};
+use shrub_rs::models::project::FunctionDefinition;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Copy, PartialEq)]
+enum VisitState {
+    InProgress,
+    Resolved,
+    Failed,
+}
+
+struct PotFunctionNode<'a> {
+    /// Name of the evergreen function this pot function translates to.
+    fn_name: String,
+    /// Name of the pot defining the function.
+    pot_name: String,
+    function: &'a BonsaiPotFunction,
+}
+
+/// Graph of which pot functions call which other pot functions.
+///
+/// Functions are identified by their call name, `pot:function`, and have an edge to each
+/// pot function called in their actions.
+pub struct PotFunctionGraph<'a> {
+    nodes: HashMap<String, PotFunctionNode<'a>>,
+    call_names: HashSet<String>,
+}
+
+impl<'a> PotFunctionGraph<'a> {
+    pub fn new(pot_map: &'a HashMap<String, BonsaiPot>) -> Self {
+        let mut nodes = HashMap::new();
+        for pot in pot_map.values() {
+            for (base_name, function) in &pot.functions {
+                nodes.insert(
+                    pot.get_call_name(base_name),
+                    PotFunctionNode {
+                        fn_name: pot.get_fn_name(base_name),
+                        pot_name: pot.name.clone(),
+                        function,
+                    },
+                );
+            }
+        }
+        let call_names = nodes.keys().cloned().collect();
+
+        Self { nodes, call_names }
+    }
+
+    /// Translate every pot function whose dependencies can be resolved.
+    ///
+    /// Calls to functions that do not exist and cycles between functions are reported in
+    /// `errors`. Functions affected by those problems are left out of the result.
+    pub fn resolve(
+        &self,
+        function_params: &HashMap<String, Vec<BonsaiPotParam>>,
+        errors: &mut BonsaiErrors,
+    ) -> HashMap<String, FunctionDefinition> {
+        let mut state = HashMap::new();
+        let mut fn_map = HashMap::new();
+
+        let mut call_names: Vec<&String> = self.call_names.iter().collect();
+        call_names.sort();
+        for call_name in call_names {
+            let mut stack = vec![];
+            self.visit(
+                call_name,
+                &mut state,
+                &mut stack,
+                &mut fn_map,
+                function_params,
+                errors,
+            );
+        }
+
+        fn_map
+    }
+
+    /// Get the pots whose commands end up in each function, keyed by call name.
+    ///
+    /// This is the pot defining the function and every pot of the functions it calls, directly or
+    /// through other functions, since those are inlined into it.
+    pub fn inlined_pots(&self) -> HashMap<String, HashSet<String>> {
+        self.call_names
+            .iter()
+            .map(|call_name| {
+                let mut pots = HashSet::new();
+                let mut visited = HashSet::new();
+                let mut pending = vec![call_name];
+                while let Some(name) = pending.pop() {
+                    if !visited.insert(name) {
+                        continue;
+                    }
+                    let node = match self.nodes.get(name) {
+                        Some(node) => node,
+                        None => continue,
+                    };
+                    pots.insert(node.pot_name.clone());
+                    for cmd in &node.function.actions {
+                        if let BonsaiCommand::Bonsai(call) = cmd {
+                            pending.push(&call.bonsai);
+                        }
+                    }
+                }
+                (call_name.clone(), pots)
+            })
+            .collect()
+    }
+
+    /// Depth-first visit of a function, resolving its dependencies before it.
+    ///
+    /// `stack` holds the chain of functions currently being visited, so reaching a function that
+    /// is still in progress means a cycle has been found.
+    fn visit(
+        &self,
+        call_name: &str,
+        state: &mut HashMap<String, VisitState>,
+        stack: &mut Vec<String>,
+        fn_map: &mut HashMap<String, FunctionDefinition>,
+        function_params: &HashMap<String, Vec<BonsaiPotParam>>,
+        errors: &mut BonsaiErrors,
+    ) -> VisitState {
+        if let Some(visit_state) = state.get(call_name) {
+            return *visit_state;
+        }
+
+        let node = &self.nodes[call_name];
+        let call_site = CallSite::PotFunction(call_name.to_string());
+        state.insert(call_name.to_string(), VisitState::InProgress);
+        stack.push(call_name.to_string());
+
+        let mut result = VisitState::Resolved;
+        for cmd in &node.function.actions {
+            let call = match cmd {
+                BonsaiCommand::Bonsai(call) => call,
+                BonsaiCommand::EvergreenNative(_) => continue,
+            };
+            if let Some(error) = find_call_error(call, &call_site, &self.call_names) {
+                errors.push(Box::new(error));
+                result = VisitState::Failed;
+                continue;
+            }
+            if let Some(params) = &self.nodes[&call.bonsai].function.params {
+                for error in check_call_params(call, &call_site, params) {
+                    errors.push(Box::new(error));
+                }
+            }
+
+            match self.visit(&call.bonsai, state, stack, fn_map, function_params, errors) {
+                VisitState::Resolved => (),
+                VisitState::Failed => result = VisitState::Failed,
+                VisitState::InProgress => {
+                    let start = stack.iter().position(|n| n == &call.bonsai).unwrap_or(0);
+                    let mut chain = stack[start..].to_vec();
+                    chain.push(call.bonsai.clone());
+                    errors.push(Box::new(CyclicDependencyError { chain }));
+                    result = VisitState::Failed;
+                }
+            }
+        }
+        stack.pop();
+
+        if result == VisitState::Resolved {
+            match node.function.try_translate(fn_map, function_params) {
+                Ok(Some(fn_def)) => {
+                    fn_map.insert(node.fn_name.clone(), fn_def);
+                }
+                Ok(None) => result = VisitState::Failed,
+                Err(err) => {
+                    errors.push(format!("{} in {}", err, call_site).into());
+                    result = VisitState::Failed;
+                }
+            }
+        }
+        state.insert(call_name.to_string(), result);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pot_map(pots: &[&str]) -> HashMap<String, BonsaiPot> {
+        pots.iter()
+            .map(|contents| {
+                let pot: BonsaiPot = serde_yaml::from_str(contents).unwrap();
+                (pot.name.clone(), pot)
+            })
+            .collect()
+    }
+
+    fn resolve(pots: &[&str]) -> (Vec<String>, Vec<String>) {
+        let pot_map = pot_map(pots);
+        let mut errors = BonsaiErrors::default();
+        let fn_map = PotFunctionGraph::new(&pot_map).resolve(&HashMap::new(), &mut errors);
+
+        let mut fn_names: Vec<String> = fn_map.keys().cloned().collect();
+        fn_names.sort();
+        let errors = errors.errors.iter().map(|e| e.to_string()).collect();
+        (fn_names, errors)
+    }
+
+    #[test]
+    fn test_resolve_inlines_called_functions() {
+        let (fn_names, errors) = resolve(&[
+            "{name: a, functions: {x: {description: x, actions: [{bonsai: b:y}]}}}",
+            "{name: b, functions: {y: {description: y, actions: [{command: shell.exec, params: {script: echo}}]}}}",
+        ]);
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(fn_names, vec!["a_x", "b_y"]);
+    }
+
+    #[test]
+    fn test_resolve_reports_cycles() {
+        let (fn_names, errors) = resolve(&[
+            "{name: a, functions: {x: {description: x, actions: [{bonsai: b:y}]}, w: {description: w, actions: [{command: shell.exec, params: {script: echo}}]}}}",
+            "{name: b, functions: {y: {description: y, actions: [{bonsai: a:x}]}}}",
+        ]);
+
+        assert_eq!(fn_names, vec!["a_w"]);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].contains("a:x -> b:y -> a:x"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn test_resolve_reports_only_the_dangling_reference() {
+        let (fn_names, errors) = resolve(&[
+            "{name: c, functions: {z: {description: z, actions: [{bonsai: c:missing}]}}}",
+            "{name: d, functions: {w: {description: w, actions: [{bonsai: c:z}]}}}",
+        ]);
+
+        assert!(fn_names.is_empty());
+        assert_eq!(
+            errors,
+            vec!["Unknown function 'missing' in pot 'c' in pot function 'c:z'"]
+        );
+    }
+}
@@ -1,8 +1,16 @@
+use crate::errors::BonsaiErrors;
 use crate::landscape::command::BonsaiCommand;
 use crate::landscape::function::{translate_functions, BonsaiFunctionDef};
-use crate::landscape::pot::{BonsaiPotDesc, BonsaiPotSource};
+use crate::landscape::graph::PotFunctionGraph;
+use crate::landscape::param::{check_pot_params, inline_call};
+use crate::landscape::pot::{
+    BonsaiPot, BonsaiPotDesc, BonsaiPotParam, BonsaiPotSource, BonsaiPotTask,
+};
+use crate::landscape::support_file::resolve_support_files;
 use crate::landscape::task::BonsaiTask;
-use crate::pot::remote_pot::copy_support_files;
+use crate::pot::loader::PotLoader;
+use crate::pot::manifest::copy_include_files;
+use crate::pot::remote_pot::{copy_manifest_support_files, copy_support_files};
 use serde::{Deserialize, Serialize};
 use shrub_rs::models::builtin::EvgCommandType;
 use shrub_rs::models::commands::EvgCommand;
@@ -10,14 +18,16 @@ use shrub_rs::models::project::{EvgModule, EvgParameter, EvgProject, FunctionDef
 use shrub_rs::models::task::EvgTask;
 use shrub_rs::models::task_group::EvgTaskGroup;
 use shrub_rs::models::variant::BuildVariant;
-use std::collections::HashMap;
+use simple_error::bail;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
-use std::path::Path;
+use std::fs::read;
+use std::path::{Path, PathBuf};
 
-use super::command::BonsaiTranslator;
+use super::command::{BonsaiTranslator, CallSite};
 
 /// Description of an Bonsai Consumer Project.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct BonsaiLandscape {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bonsai: Option<Vec<BonsaiPotDesc>>,
@@ -66,64 +76,116 @@ pub struct BonsaiLandscape {
     pub parameters: Option<Vec<EvgParameter>>,
 }
 
-impl Default for BonsaiLandscape {
-    fn default() -> Self {
-        BonsaiLandscape {
-            bonsai: None,
-            buildvariants: vec![],
-            tasks: vec![],
-            task_groups: None,
-            functions: Default::default(),
-            pre: None,
-            post: None,
-            timeout: None,
-            modules: None,
-            stepback: None,
-            pre_error_fails_task: None,
-            oom_tracker: None,
-            command_type: None,
-            ignore: None,
-            parameters: None,
-        }
+impl BonsaiLandscape {
+    /// Load the pots from all sources of this landscape without translating anything.
+    pub fn resolve_sources(&self, pot_loader: &mut PotLoader) -> Result<(), Box<dyn Error>> {
+        let mut errors = BonsaiErrors::default();
+        load_pots(&self.bonsai, pot_loader, &mut errors);
+        errors.into_result(())
     }
-}
 
-impl BonsaiLandscape {
-    pub fn copy_remote_support_files(
+    /// Copy the support files of all used pots into the given directory.
+    ///
+    /// Returns the paths of the files copied, relative to `destination_dir`. The files of aliased
+    /// pots are copied to a directory named after the alias. It is an error for different
+    /// sources to provide the same file with different contents, since only one could be kept.
+    pub fn copy_support_files(
         &self,
         destination_dir: &Path,
         bonsai_translator: &BonsaiTranslator,
-    ) -> Result<(), Box<dyn Error>> {
+        pot_loader: &mut PotLoader,
+    ) -> Result<BTreeSet<PathBuf>, Box<dyn Error>> {
+        let mut copied: BTreeMap<PathBuf, (String, Vec<u8>)> = BTreeMap::new();
+        let mut overlapping = vec![];
         if let Some(bonsai_pot_list) = &self.bonsai {
             for pot_descriptor in bonsai_pot_list {
-                if let BonsaiPotSource::Github(github_source) = &pot_descriptor.source {
-                    copy_support_files(
-                        github_source,
-                        destination_dir,
-                        &bonsai_translator.seen_pots,
-                    )?;
+                let used_pots = &pot_descriptor.get_used_pots(&bonsai_translator.seen_pots);
+                let files = match &pot_descriptor.source {
+                    BonsaiPotSource::Github(github_source) => {
+                        copy_support_files(github_source, destination_dir, used_pots, pot_loader)?
+                    }
+                    BonsaiPotSource::Git(git_source) => {
+                        copy_support_files(git_source, destination_dir, used_pots, pot_loader)?
+                    }
+                    BonsaiPotSource::Local(local_source) => {
+                        let path = Path::new(&local_source.path);
+                        if path.is_dir() {
+                            copy_manifest_support_files(path, destination_dir, used_pots)?
+                        } else {
+                            copy_pot_support_files(path, destination_dir, used_pots)?
+                        }
+                    }
+                };
+                let source = pot_descriptor.describe(pot_loader);
+                for file in files {
+                    let contents = read(destination_dir.join(&file))?;
+                    match copied.get(&file) {
+                        Some((other, other_contents))
+                            if other != &source && other_contents != &contents =>
+                        {
+                            overlapping.push(format!(
+                                "'{}' from '{}' and '{}'",
+                                file.display(),
+                                other,
+                                source
+                            ))
+                        }
+                        Some(_) => (),
+                        None => {
+                            copied.insert(file, (source.clone(), contents));
+                        }
+                    }
                 }
             }
         }
 
-        Ok(())
+        if !overlapping.is_empty() {
+            bail!(
+                "Support files are provided by more than one source: {}",
+                overlapping.join(", ")
+            );
+        }
+        Ok(copied.into_keys().collect())
     }
 
     pub fn create_evg_project(
         &self,
         bonsai_translator: &mut BonsaiTranslator,
+        pot_loader: &mut PotLoader,
     ) -> Result<EvgProject, Box<dyn Error>> {
-        let pot_map = PotMap::new(&self.bonsai)?;
+        let pot_map = PotMap::new(
+            &self.bonsai,
+            pot_loader,
+            &bonsai_translator.support_files_path,
+            &mut bonsai_translator.errors,
+        );
+        bonsai_translator
+            .known_functions
+            .extend(pot_map.known_calls.iter().cloned());
+        bonsai_translator
+            .function_params
+            .extend(pot_map.function_params.clone());
+        bonsai_translator
+            .inlined_pots
+            .extend(pot_map.inlined_pots.clone());
+        bonsai_translator
+            .substituted_pots
+            .extend(pot_map.substituted_pots.iter().cloned());
+        bonsai_translator
+            .task_templates
+            .extend(pot_map.task_templates.clone());
 
         let tasks = self.translate_tasks(bonsai_translator);
         let pre = self.translate_pre(bonsai_translator);
         let post = self.translate_post(bonsai_translator);
         let timeout = self.translate_timeout(bonsai_translator);
 
-        Ok(EvgProject {
+        let functions = pot_map.get_function_definitions(&self.functions, bonsai_translator);
+
+        let evg_project = EvgProject {
             buildvariants: self.buildvariants.clone(),
 
-            functions: pot_map.get_function_definitions(&self.functions, bonsai_translator),
+            functions,
 
             tasks,
             task_groups: self.task_groups.as_ref().map(|tg| tg.to_vec()),
@@ -138,34 +200,30 @@ impl BonsaiLandscape {
             command_type: self.command_type.as_ref().cloned(),
             ignore: self.ignore.as_ref().cloned(),
             parameters: self.parameters.as_ref().cloned(),
-        })
+        };
+
+        std::mem::take(&mut bonsai_translator.errors).into_result(evg_project)
     }
 
     fn translate_pre(&self, bonsai_translator: &mut BonsaiTranslator) -> Option<Vec<EvgCommand>> {
-        if let Some(pre_commands) = &self.pre {
-            Some(bonsai_translator.translate_command_list(pre_commands))
-        } else {
-            None
-        }
+        self.pre.as_ref().map(|pre_commands| {
+            bonsai_translator.translate_command_list(pre_commands, &CallSite::Pre)
+        })
     }
 
     fn translate_post(&self, bonsai_translator: &mut BonsaiTranslator) -> Option<Vec<EvgCommand>> {
-        if let Some(post_commands) = &self.post {
-            Some(bonsai_translator.translate_command_list(post_commands))
-        } else {
-            None
-        }
+        self.post.as_ref().map(|post_commands| {
+            bonsai_translator.translate_command_list(post_commands, &CallSite::Post)
+        })
     }
 
     fn translate_timeout(
         &self,
         bonsai_translator: &mut BonsaiTranslator,
     ) -> Option<Vec<EvgCommand>> {
-        if let Some(timeout_commands) = &self.timeout {
-            Some(bonsai_translator.translate_command_list(timeout_commands))
-        } else {
-            None
-        }
+        self.timeout.as_ref().map(|timeout_commands| {
+            bonsai_translator.translate_command_list(timeout_commands, &CallSite::Timeout)
+        })
     }
 
     fn translate_tasks(&self, bonsai_translator: &mut BonsaiTranslator) -> Vec<EvgTask> {
@@ -176,26 +234,124 @@ impl BonsaiLandscape {
     }
 }
 
+/// Copy the support files declared by a local pot file, if the pot is used.
+fn copy_pot_support_files(
+    pot_file: &Path,
+    destination_dir: &Path,
+    used_pots: &HashMap<String, PathBuf>,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let pot = BonsaiPot::from_path(pot_file)?;
+    match (
+        &pot.include_files,
+        pot_file.parent(),
+        used_pots.get(&pot.name),
+    ) {
+        (Some(files), Some(base_dir), Some(support_files_dir)) => {
+            let excludes = pot.exclude_files.as_deref().unwrap_or_default();
+            let destination_dir = destination_dir.join(support_files_dir);
+            let copied = copy_include_files(files, excludes, base_dir, &destination_dir)?;
+            Ok(copied
+                .into_iter()
+                .map(|f| support_files_dir.join(f))
+                .collect())
+        }
+        _ => Ok(vec![]),
+    }
+}
+
+/// Load all pots from the given sources, including sources declared by those pots.
+fn load_pots(
+    bonsai: &Option<Vec<BonsaiPotDesc>>,
+    pot_loader: &mut PotLoader,
+    errors: &mut BonsaiErrors,
+) -> HashMap<String, BonsaiPot> {
+    let mut pot_map = HashMap::new();
+    if let Some(bonsai_pot_list) = bonsai {
+        for pot_desc in bonsai_pot_list {
+            if let Err(err) = pot_desc.update_pot_map(&mut pot_map, pot_loader, errors) {
+                errors.push(
+                    format!("Could not load pots from '{}': {}", pot_desc.source, err).into(),
+                );
+            }
+        }
+    }
+
+    pot_map
+}
+
 struct PotMap {
     pub function_map: HashMap<String, FunctionDefinition>,
+    /// Names of all declared pot functions as they are called, `pot:function`.
+    ///
+    /// Functions that could not be resolved are included, so calls to them are not reported as
+    /// unknown, only the problem that kept them from being resolved is.
+    pub known_calls: Vec<String>,
+    /// Parameters declared by pot functions, keyed by `pot:function`.
+    pub function_params: HashMap<String, Vec<BonsaiPotParam>>,
+    /// Pots whose commands are inlined into each pot function, keyed by `pot:function`.
+    pub inlined_pots: HashMap<String, HashSet<String>>,
+    /// Names of the pots whose params are substituted by default.
+    pub substituted_pots: Vec<String>,
+    /// Task templates defined by pots, keyed by `pot:task`.
+    pub task_templates: HashMap<String, BonsaiPotTask>,
 }
 
 impl PotMap {
-    fn new(bonsai: &Option<Vec<BonsaiPotDesc>>) -> Result<Self, Box<dyn Error>> {
-        let mut pot_map = HashMap::new();
-        if let Some(bonsai_pot_list) = bonsai {
-            bonsai_pot_list
-                .iter()
-                .try_for_each(|p| p.update_pot_map(&mut pot_map))?;
+    fn new(
+        bonsai: &Option<Vec<BonsaiPotDesc>>,
+        pot_loader: &mut PotLoader,
+        support_files_path: &str,
+        errors: &mut BonsaiErrors,
+    ) -> Self {
+        let mut pot_map = load_pots(bonsai, pot_loader, errors);
+        for pot in pot_map.values_mut() {
+            check_pot_params(pot, errors);
+            resolve_support_files(pot, support_files_path, errors);
         }
+        let function_params = pot_map
+            .values()
+            .flat_map(|pot| {
+                pot.functions.iter().filter_map(move |(name, function)| {
+                    function
+                        .params
+                        .as_ref()
+                        .map(|params| (pot.get_call_name(name), params.clone()))
+                })
+            })
+            .collect();
+        let graph = PotFunctionGraph::new(&pot_map);
+        let function_map = graph.resolve(&function_params, errors);
+        let inlined_pots = graph.inlined_pots();
 
-        let mut function_map = HashMap::new();
-        let mut still_updating = true;
-        while still_updating {
-            still_updating = pot_map.values().any(|p| p.update_fn_map(&mut function_map));
-        }
+        let known_calls = pot_map
+            .values()
+            .flat_map(|p| p.get_call_names())
+            .map(|(call_name, _)| call_name)
+            .collect();
+
+        let substituted_pots = pot_map
+            .values()
+            .filter(|pot| pot.substitute_params)
+            .map(|pot| pot.name.clone())
+            .collect();
+
+        let task_templates = pot_map
+            .values()
+            .flat_map(|pot| {
+                pot.tasks
+                    .iter()
+                    .map(move |(name, task)| (pot.get_call_name(name), task.clone()))
+            })
+            .collect();
 
-        Ok(Self { function_map })
+        Self {
+            function_map,
+            known_calls,
+            function_params,
+            inlined_pots,
+            substituted_pots,
+            task_templates,
+        }
     }
 
     fn translate_functions(
@@ -225,15 +381,37 @@ impl PotMap {
         // self.function_map.iter().filter(|(name, _)| bonsai_translator.is_used(name)).collect()
     }
 
+    /// Create the specialized functions that calls with substituted params were translated to.
+    fn get_specialized_functions(
+        &self,
+        bonsai_translator: &mut BonsaiTranslator,
+    ) -> HashMap<String, FunctionDefinition> {
+        let mut specialized_functions = HashMap::new();
+        for (name, call) in bonsai_translator.specialized_calls.clone() {
+            if let Some(fn_def) = self.function_map.get(&call.get_fn_name()) {
+                match inline_call(&call, fn_def) {
+                    Ok(cmds) => {
+                        specialized_functions.insert(name, FunctionDefinition::CommandList(cmds));
+                    }
+                    Err(err) => bonsai_translator
+                        .errors
+                        .push(format!("{} in call to '{}'", err, call.bonsai).into()),
+                }
+            }
+        }
+        specialized_functions
+    }
+
     fn get_function_definitions(
         &self,
         functions: &Option<HashMap<String, BonsaiFunctionDef>>,
         bonsai_translator: &mut BonsaiTranslator,
     ) -> HashMap<String, FunctionDefinition> {
-        let functions = self.translate_functions(&functions, bonsai_translator);
+        let functions = self.translate_functions(functions, bonsai_translator);
         functions
             .into_iter()
             .chain(self.get_used_functions(bonsai_translator))
+            .chain(self.get_specialized_functions(bonsai_translator))
             .collect()
     }
 }
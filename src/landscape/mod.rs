@@ -1,5 +1,9 @@
 pub mod command;
 pub mod function;
+pub mod graph;
+#[allow(clippy::module_inception)]
 pub mod landscape;
+pub mod param;
 pub mod pot;
+pub mod support_file;
 pub mod task;
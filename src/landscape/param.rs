@@ -0,0 +1,461 @@
+use crate::errors::{BonsaiErrors, BonsaiParamError};
+use crate::landscape::command::{BonsaiCall, CallSite};
+use crate::landscape::pot::{BonsaiPot, BonsaiPotParam, ParamType};
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yaml::Value;
+use shrub_rs::models::commands::EvgCommand;
+use shrub_rs::models::params::ParamValue;
+use shrub_rs::models::project::FunctionDefinition;
+use simple_error::bail;
+use std::collections::HashMap;
+use std::error::Error;
+
+const EXPANSION_START: &str = "${";
+const EXPANSION_END: &str = "}";
+
+/// Check if a value is left for evergreen to expand at runtime, so its type cannot be known.
+fn is_expansion(value: &ParamValue) -> bool {
+    matches!(value, ParamValue::String(s) if s.contains(EXPANSION_START))
+}
+
+/// Get the string form of a scalar value, `None` for lists and maps.
+fn scalar_value(value: &ParamValue) -> Option<String> {
+    match value {
+        ParamValue::Bool(_)
+        | ParamValue::String(_)
+        | ParamValue::Number(_)
+        | ParamValue::Float(_) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Check a value against the type of a parameter, returning a description of what was expected
+/// if it does not match.
+fn check_type(param: &BonsaiPotParam, value: &ParamValue) -> Result<(), String> {
+    let scalar = scalar_value(value);
+    let matches = match param.param_type {
+        None => true,
+        Some(ParamType::String) => scalar.is_some(),
+        Some(ParamType::Bool) => match value {
+            ParamValue::Bool(_) => true,
+            ParamValue::String(s) => s == "true" || s == "false",
+            _ => false,
+        },
+        Some(ParamType::Int) => match value {
+            ParamValue::Number(_) => true,
+            ParamValue::Float(f) => f.fract() == 0.0,
+            ParamValue::String(s) => s.parse::<i64>().is_ok(),
+            _ => false,
+        },
+        Some(ParamType::Enum) => {
+            let values = param.values.as_deref().unwrap_or_default();
+            scalar.is_some_and(|s| values.contains(&s))
+        }
+    };
+    if !matches {
+        return Err(match param.param_type {
+            Some(ParamType::Enum) => {
+                let values = param.values.as_deref().unwrap_or_default();
+                format!("one of '{}'", values.join("', '"))
+            }
+            Some(ParamType::String) => "a string".to_string(),
+            Some(ParamType::Bool) => "true or false".to_string(),
+            Some(ParamType::Int) => "an integer".to_string(),
+            None => "a value".to_string(),
+        });
+    }
+
+    if let Some(pattern) = &param.pattern {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|err| format!("a valid pattern, '{}' is invalid: {}", pattern, err))?;
+        if !scalar_value(value).is_some_and(|s| regex.is_match(&s)) {
+            return Err(format!("a value matching '{}'", pattern));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the parameters given in a call against the parameters the called function declares.
+///
+/// Values that contain evergreen expansions are only known at runtime and are not type checked.
+pub fn check_call_params(
+    bonsai_call: &BonsaiCall,
+    call_site: &CallSite,
+    params: &[BonsaiPotParam],
+) -> Vec<BonsaiParamError> {
+    let mut errors = vec![];
+    let given = bonsai_call.params.clone().unwrap_or_default();
+
+    let mut unknown: Vec<&String> = given
+        .keys()
+        .filter(|name| !params.iter().any(|p| &p.name == *name))
+        .collect();
+    unknown.sort();
+    for name in unknown {
+        errors.push(BonsaiParamError::Unknown {
+            call: bonsai_call.bonsai.clone(),
+            param: name.clone(),
+            call_site: call_site.clone(),
+        });
+    }
+
+    for param in params {
+        match given.get(&param.name) {
+            Some(value) if !is_expansion(value) => {
+                if let Err(expected) = check_type(param, value) {
+                    errors.push(BonsaiParamError::Invalid {
+                        call: bonsai_call.bonsai.clone(),
+                        param: param.name.clone(),
+                        value: value.to_string(),
+                        expected,
+                        call_site: call_site.clone(),
+                    });
+                }
+            }
+            Some(_) => (),
+            None if param.required && param.default.is_none() => {
+                errors.push(BonsaiParamError::Missing {
+                    call: bonsai_call.bonsai.clone(),
+                    param: param.name.clone(),
+                    call_site: call_site.clone(),
+                });
+            }
+            None => (),
+        }
+    }
+
+    errors
+}
+
+/// Check the parameters declared by a pot function or task template, returning a description
+/// of each problem found.
+///
+/// Enums must declare their values, patterns must be valid and defaults must match the type of
+/// their parameter.
+pub fn check_param_declarations(params: &[BonsaiPotParam], call_site: &CallSite) -> Vec<String> {
+    let mut errors = vec![];
+    for param in params {
+        if param.param_type == Some(ParamType::Enum)
+            && param.values.as_ref().is_none_or(|v| v.is_empty())
+        {
+            errors.push(format!(
+                "Parameter '{}' of {} is an enum but does not list any values",
+                param.name, call_site
+            ));
+            continue;
+        }
+        if let Some(pattern) = &param.pattern {
+            if let Err(err) = Regex::new(pattern) {
+                errors.push(format!(
+                    "Invalid pattern '{}' for parameter '{}' of {}: {}",
+                    pattern, param.name, call_site, err
+                ));
+                continue;
+            }
+        }
+        if let Some(default) = param.default.as_ref().filter(|d| !is_expansion(d)) {
+            if let Err(expected) = check_type(param, default) {
+                errors.push(format!(
+                    "Invalid default value '{}' for parameter '{}' of {}, expected {}",
+                    default, param.name, call_site, expected
+                ));
+            }
+        }
+    }
+    errors
+}
+
+/// Check the parameters declared by every function and task template of a pot.
+pub fn check_pot_params(pot: &BonsaiPot, errors: &mut BonsaiErrors) {
+    let functions = pot.functions.iter().map(|(name, f)| {
+        let call_site = CallSite::PotFunction(pot.get_call_name(name));
+        (call_site, &f.params)
+    });
+    let tasks = pot.tasks.iter().map(|(name, t)| {
+        let call_site = CallSite::PotTask(pot.get_call_name(name));
+        (call_site, &t.params)
+    });
+    let mut found: Vec<String> = functions
+        .chain(tasks)
+        .filter_map(|(call_site, params)| {
+            params
+                .as_ref()
+                .map(|params| check_param_declarations(params, &call_site))
+        })
+        .flatten()
+        .collect();
+    found.sort();
+    for error in found {
+        errors.push(error.into());
+    }
+}
+
+/// Get a copy of the call with the default value of every parameter it does not give.
+pub fn with_defaults(bonsai_call: &BonsaiCall, params: &[BonsaiPotParam]) -> BonsaiCall {
+    let mut call = bonsai_call.clone();
+    for param in params {
+        if let Some(default) = &param.default {
+            let given = call.params.get_or_insert_with(Default::default);
+            given
+                .entry(param.name.clone())
+                .or_insert_with(|| default.clone());
+        }
+    }
+    call
+}
+
+/// Replace evergreen expansions of the given parameters, `${name}` or `${name|default}`, in a
+/// string. Expansions of other names are left for evergreen to expand at runtime.
+///
+/// Returns `None` if nothing was replaced. Only scalar values can be substituted, since lists
+/// and maps have no string form.
+fn substitute_string(
+    value: &str,
+    params: &HashMap<String, ParamValue>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut substituted = String::new();
+    let mut found = false;
+    let mut rest = value;
+    while let Some(start) = rest.find(EXPANSION_START) {
+        let after_start = &rest[start + EXPANSION_START.len()..];
+        let end = match after_start.find(EXPANSION_END) {
+            Some(end) => end,
+            None => break,
+        };
+
+        let expansion = &after_start[..end];
+        let name = expansion.split('|').next().unwrap_or(expansion).trim();
+        substituted.push_str(&rest[..start]);
+        match params.get(name) {
+            Some(param_value) => match scalar_value(param_value) {
+                Some(scalar) => {
+                    substituted.push_str(&scalar);
+                    found = true;
+                }
+                None => bail!(
+                    "Cannot substitute '{}' into '{}', only strings, numbers and booleans can be substituted",
+                    name,
+                    value
+                ),
+            },
+            None => substituted
+                .push_str(&rest[start..start + EXPANSION_START.len() + end + EXPANSION_END.len()]),
+        }
+        rest = &after_start[end + EXPANSION_END.len()..];
+    }
+    substituted.push_str(rest);
+
+    if found {
+        Ok(Some(substituted))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Substitute parameters into every string of a YAML value, returning whether anything changed.
+fn substitute_value(
+    value: &mut Value,
+    params: &HashMap<String, ParamValue>,
+) -> Result<bool, Box<dyn Error>> {
+    match value {
+        Value::String(string) => match substitute_string(string, params)? {
+            Some(substituted) => {
+                *string = substituted;
+                Ok(true)
+            }
+            None => Ok(false),
+        },
+        Value::Sequence(sequence) => {
+            let mut changed = false;
+            for v in sequence.iter_mut() {
+                changed |= substitute_value(v, params)?;
+            }
+            Ok(changed)
+        }
+        Value::Mapping(mapping) => {
+            let mut changed = false;
+            for (_, v) in mapping.iter_mut() {
+                changed |= substitute_value(v, params)?;
+            }
+            Ok(changed)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Substitute the given parameters into a command.
+pub fn substitute_command<T: Serialize + DeserializeOwned + Clone>(
+    command: &T,
+    params: &HashMap<String, ParamValue>,
+) -> Result<T, Box<dyn Error>> {
+    let mut value = serde_yaml::to_value(command)?;
+    if substitute_value(&mut value, params)? {
+        Ok(serde_yaml::from_value(value)?)
+    } else {
+        Ok(command.clone())
+    }
+}
+
+/// Get the commands of a called function with the parameters given in the call substituted in.
+///
+/// Evergreen functions cannot call other functions, so calls between functions are made by
+/// copying the commands of the called function into the caller.
+pub fn inline_call(
+    bonsai_call: &BonsaiCall,
+    fn_def: &FunctionDefinition,
+) -> Result<Vec<EvgCommand>, Box<dyn Error>> {
+    let commands = match fn_def {
+        FunctionDefinition::CommandList(cmd_list) => cmd_list.to_vec(),
+        FunctionDefinition::SingleCommand(cmd) => vec![cmd.clone()],
+    };
+
+    match &bonsai_call.params {
+        Some(params) if !params.is_empty() => commands
+            .iter()
+            .map(|cmd| substitute_command(cmd, params))
+            .collect(),
+        _ => Ok(commands),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: &str = r#"
+- name: version
+  description: version
+  required: true
+  pattern: "[0-9]+\\.[0-9]+"
+- name: port
+  description: port
+  type: int
+  default: 27017
+- name: topology
+  description: topology
+  type: enum
+  values: [standalone, replset]
+- name: auth
+  description: auth
+  type: bool
+- name: name
+  description: name
+  type: string
+"#;
+
+    fn call(params: &str) -> BonsaiCall {
+        BonsaiCall {
+            bonsai: "db:start".to_string(),
+            params: serde_yaml::from_str(params).unwrap(),
+            substitute_params: None,
+        }
+    }
+
+    fn check(params: &str) -> Vec<String> {
+        let declared: Vec<BonsaiPotParam> = serde_yaml::from_str(PARAMS).unwrap();
+        let call_site = CallSite::Task("t".to_string());
+        check_call_params(&call(params), &call_site, &declared)
+            .iter()
+            .map(|err| err.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_check_call_params_accepts_valid_values() {
+        assert!(check("{version: '4.4'}").is_empty());
+        assert!(check("{version: '4.4', port: 1234, topology: replset, auth: true}").is_empty());
+        assert!(check("{version: '4.4', port: '1234', auth: 'false', name: 3}").is_empty());
+    }
+
+    #[test]
+    fn test_check_call_params_skips_expansions() {
+        assert!(check("{version: '${version}', port: '${port|27017}'}").is_empty());
+    }
+
+    #[test]
+    fn test_check_call_params_reports_missing_and_unknown() {
+        assert_eq!(
+            check("{colour: red, size: 1}"),
+            vec![
+                "Unknown parameter 'colour' in call to 'db:start' in task 't'",
+                "Unknown parameter 'size' in call to 'db:start' in task 't'",
+                "Missing required parameter 'version' in call to 'db:start' in task 't'",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_call_params_reports_invalid_values() {
+        assert_eq!(
+            check("{version: latest, port: 1.5, topology: sharded, auth: maybe, name: [a]}"),
+            vec![
+                "Invalid value 'latest' for parameter 'version' in call to 'db:start' in task 't', expected a value matching '[0-9]+\\.[0-9]+'",
+                "Invalid value '1.5' for parameter 'port' in call to 'db:start' in task 't', expected an integer",
+                "Invalid value 'sharded' for parameter 'topology' in call to 'db:start' in task 't', expected one of 'standalone', 'replset'",
+                "Invalid value 'maybe' for parameter 'auth' in call to 'db:start' in task 't', expected true or false",
+                "Invalid value 'a' for parameter 'name' in call to 'db:start' in task 't', expected a string",
+            ]
+        );
+    }
+
+    fn substitute(value: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let params = call("{version: '4.4', port: 27017, auth: false, hosts: [a, b], env: {k: v}}")
+            .params
+            .unwrap();
+        substitute_string(value, &params)
+    }
+
+    #[test]
+    fn test_substitute_string() {
+        assert_eq!(
+            substitute("start ${version} ${port|1234} ${auth}").unwrap(),
+            Some("start 4.4 27017 false".to_string())
+        );
+        assert_eq!(
+            substitute("${ version }-${other}-${other|x}").unwrap(),
+            Some("4.4-${other}-${other|x}".to_string())
+        );
+        assert_eq!(substitute("${other} $version ${version").unwrap(), None);
+        assert_eq!(substitute("no expansions").unwrap(), None);
+    }
+
+    #[test]
+    fn test_substitute_string_rejects_lists_and_maps() {
+        assert!(substitute("hosts: ${hosts}").is_err());
+        assert!(substitute("env: ${env|none}").is_err());
+    }
+
+    fn check_declarations(params: &str) -> Vec<String> {
+        let declared: Vec<BonsaiPotParam> = serde_yaml::from_str(params).unwrap();
+        check_param_declarations(&declared, &CallSite::PotFunction("db:start".to_string()))
+    }
+
+    #[test]
+    fn test_check_param_declarations_accepts_valid_declarations() {
+        assert!(check_declarations(PARAMS).is_empty());
+        assert!(check_declarations(
+            "[{name: p, description: p, type: int, default: '${port|1}'}, {name: e, description: e, type: enum, values: [a, b], default: b}]"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_check_param_declarations_reports_invalid_declarations() {
+        assert_eq!(
+            check_declarations(
+                "[{name: port, description: p, type: int, default: abc}, {name: mode, description: m, type: enum}, {name: v, description: v, pattern: '[0-9]+', default: x}]"
+            ),
+            vec![
+                "Invalid default value 'abc' for parameter 'port' of pot function 'db:start', expected an integer",
+                "Parameter 'mode' of pot function 'db:start' is an enum but does not list any values",
+                "Invalid default value 'x' for parameter 'v' of pot function 'db:start', expected a value matching '[0-9]+'",
+            ]
+        );
+        let errors = check_declarations("[{name: v, description: v, pattern: '['}]");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Invalid pattern '[' for parameter 'v'"));
+    }
+}
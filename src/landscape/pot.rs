@@ -1,22 +1,35 @@
+use crate::errors::BonsaiErrors;
 use crate::landscape::command::BonsaiCommand;
-use crate::pot::remote_pot::get_remote_pots;
+use crate::landscape::param::{inline_call, with_defaults};
+use crate::pot::credentials::GitTransport;
+use crate::pot::loader::PotLoader;
+use crate::pot::remote_pot::{get_remote_pots, read_manifest_pots};
+use crate::pot::update::short_commit;
 use serde::{Deserialize, Serialize};
+use shrub_rs::models::params::ParamValue;
 use shrub_rs::models::project::FunctionDefinition;
-use std::collections::HashMap;
+use shrub_rs::models::task::TaskDependency;
+use simple_error::bail;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::fs::read_to_string;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LocalSourceDesc {
+    /// Path to a single pot file, or to a directory with a manifest listing its pots.
     pub path: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "lowercase")]
 pub enum GithubVersionSpec {
     Revision(String),
     Branch(String),
+    Tag(String),
+    /// Semantic version requirement, resolved to the highest matching tag.
+    Version(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -25,6 +38,137 @@ pub struct GithubSourceDesc {
     pub repo: String,
     #[serde(flatten)]
     pub version: Option<GithubVersionSpec>,
+    /// Transport to clone with, the configured default is used if not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<GitTransport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GitSourceDesc {
+    /// Url of the repository, any url supported by git can be used (ssh, https or file).
+    pub url: String,
+    #[serde(flatten)]
+    pub version: Option<GithubVersionSpec>,
+}
+
+/// Identity of a remote repository pots are loaded from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum RemoteId {
+    Github { owner: String, repo: String },
+    Git { url: String },
+}
+
+impl RemoteId {
+    /// Get the url to clone the repository from.
+    pub fn get_url(&self, transport: GitTransport) -> String {
+        match (self, transport) {
+            (RemoteId::Github { owner, repo }, GitTransport::Ssh) => {
+                format!("git@github.com:{}/{}.git", owner, repo)
+            }
+            (RemoteId::Github { owner, repo }, GitTransport::Https) => {
+                format!("https://github.com/{}/{}.git", owner, repo)
+            }
+            (RemoteId::Git { url }, _) => url.clone(),
+        }
+    }
+
+    /// Get a relative path that uniquely identifies the repository, `host/owner/repo`.
+    ///
+    /// The same repository maps to the same path regardless of the transport used to reach it.
+    pub fn get_cache_path(&self) -> PathBuf {
+        let (host, path) = match self {
+            RemoteId::Github { owner, repo } => {
+                ("github.com".to_string(), format!("{}/{}", owner, repo))
+            }
+            RemoteId::Git { url } => split_url(url),
+        };
+
+        let mut segments: Vec<&str> = path
+            .split('/')
+            .filter(|s| !s.is_empty() && *s != "." && *s != "..")
+            .collect();
+        if let Some(last) = segments.pop() {
+            segments.push(last.trim_end_matches(".git"));
+        }
+
+        let mut cache_path = PathBuf::from(host.replace(':', "_"));
+        cache_path.extend(segments);
+        cache_path
+    }
+}
+
+/// Split a git url into its host and the path of the repository on that host.
+///
+/// Handles urls with a scheme (`https://host/path`), scp-like urls (`git@host:path`) and local
+/// paths, which are given the host `local`.
+pub fn split_url(url: &str) -> (String, String) {
+    let strip_user = |host: &str| host.rsplit('@').next().unwrap_or(host).to_string();
+
+    if let Some((_, rest)) = url.split_once("://") {
+        let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+        let host = strip_user(host);
+        if host.is_empty() {
+            ("local".to_string(), path.to_string())
+        } else {
+            (host, path.to_string())
+        }
+    } else {
+        match url.split_once(':') {
+            Some((host, path)) if !host.contains('/') => (strip_user(host), path.to_string()),
+            _ => ("local".to_string(), url.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for RemoteId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteId::Github { owner, repo } => write!(f, "{}/{}", owner, repo),
+            RemoteId::Git { url } => write!(f, "{}", url),
+        }
+    }
+}
+
+/// A source of pots that is stored in a remote git repository.
+pub trait RemoteSourceDesc {
+    fn get_remote_id(&self) -> RemoteId;
+
+    fn get_version(&self) -> &Option<GithubVersionSpec>;
+
+    /// Transport to clone with, the configured default is used if `None`.
+    fn get_transport(&self) -> Option<GitTransport> {
+        None
+    }
+}
+
+impl RemoteSourceDesc for GithubSourceDesc {
+    fn get_remote_id(&self) -> RemoteId {
+        RemoteId::Github {
+            owner: self.owner.clone(),
+            repo: self.repo.clone(),
+        }
+    }
+
+    fn get_version(&self) -> &Option<GithubVersionSpec> {
+        &self.version
+    }
+
+    fn get_transport(&self) -> Option<GitTransport> {
+        self.transport
+    }
+}
+
+impl RemoteSourceDesc for GitSourceDesc {
+    fn get_remote_id(&self) -> RemoteId {
+        RemoteId::Git {
+            url: self.url.clone(),
+        }
+    }
+
+    fn get_version(&self) -> &Option<GithubVersionSpec> {
+        &self.version
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -32,18 +176,60 @@ pub struct GithubSourceDesc {
 pub enum BonsaiPotSource {
     Local(LocalSourceDesc),
     Github(GithubSourceDesc),
+    Git(GitSourceDesc),
+}
+
+impl fmt::Display for BonsaiPotSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BonsaiPotSource::Local(local) => write!(f, "{}", local.path),
+            BonsaiPotSource::Github(github) => write!(f, "{}", github.get_remote_id()),
+            BonsaiPotSource::Git(git) => write!(f, "{}", git.get_remote_id()),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BonsaiPotDesc {
     #[serde(flatten)]
     pub source: BonsaiPotSource,
+    /// Names of the pots to import from this source, all pots are imported if not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pots: Option<Vec<String>>,
+    /// New names to import pots of this source under, keyed by the name the pot defines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<HashMap<String, String>>,
+}
+
+/// Type of value a pot function parameter accepts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    String,
+    Bool,
+    Int,
+    /// One of the strings listed in the `values` of the parameter.
+    Enum,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BonsaiPotParam {
     pub name: String,
     pub description: String,
+    /// Whether every call must give a value for the parameter.
+    #[serde(default)]
+    pub required: bool,
+    /// Value used when a call does not give one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<ParamValue>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub param_type: Option<ParamType>,
+    /// Values allowed for an `enum` parameter.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+    /// Regular expression values of the parameter must fully match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -55,10 +241,14 @@ pub struct BonsaiPotFunction {
 }
 
 impl BonsaiPotFunction {
+    /// Translate the function, copying in the commands of the pot functions it calls.
+    ///
+    /// Returns `None` if a called function has not been translated.
     pub fn try_translate(
         &self,
         fn_map: &HashMap<String, FunctionDefinition>,
-    ) -> Option<FunctionDefinition> {
+        function_params: &HashMap<String, Vec<BonsaiPotParam>>,
+    ) -> Result<Option<FunctionDefinition>, Box<dyn Error>> {
         let mut command_list = vec![];
         for cmd in &self.actions {
             match cmd {
@@ -66,33 +256,73 @@ impl BonsaiPotFunction {
                     command_list.push(evg_cmd.clone());
                 }
                 BonsaiCommand::Bonsai(bonsai_call) => {
-                    if let Some(fn_cmds) = fn_map.get(&bonsai_call.get_fn_name()) {
-                        match fn_cmds {
-                            FunctionDefinition::CommandList(cmd_list) => {
-                                command_list.extend(cmd_list.to_vec());
-                            }
-                            FunctionDefinition::SingleCommand(cmd) => {
-                                command_list.push(cmd.clone());
-                            }
-                        }
-                    } else {
-                        return None;
-                    }
+                    let fn_def = match fn_map.get(&bonsai_call.get_fn_name()) {
+                        Some(fn_def) => fn_def,
+                        None => return Ok(None),
+                    };
+                    let call = match function_params.get(&bonsai_call.bonsai) {
+                        Some(params) => with_defaults(bonsai_call, params),
+                        None => bonsai_call.clone(),
+                    };
+                    command_list.extend(inline_call(&call, fn_def)?);
                 }
             }
         }
 
-        Some(FunctionDefinition::CommandList(command_list))
+        Ok(Some(FunctionDefinition::CommandList(command_list)))
     }
 }
 
+/// Template of a task, which landscapes can create tasks from with `bonsai_task: pot:task`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BonsaiPotTask {
+    pub description: String,
+    /// Commands of the task, params are substituted into them when a task is created.
+    pub commands: Vec<BonsaiCommand>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<Vec<BonsaiPotParam>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<TaskDependency>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patchable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stepback: Option<bool>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BonsaiPot {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub bonsai: Option<Vec<BonsaiPotDesc>>,
-    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub functions: HashMap<String, BonsaiPotFunction>,
+    /// Templates of tasks that landscapes can create from this pot.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tasks: HashMap<String, BonsaiPotTask>,
+    /// Support files, relative to the pot file, to copy with the generated configuration.
+    ///
+    /// Pots listed in a manifest declare them in the manifest instead, relative to the manifest,
+    /// and they are filled in from there when the pot is loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_files: Option<Vec<String>>,
+    /// Glob patterns of files to leave out of `include_files`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_files: Option<Vec<String>>,
+    /// Substitute the params of calls to the functions of this pot into specialized copies of
+    /// the functions instead of passing them as expansions.
+    #[serde(default)]
+    pub substitute_params: bool,
+    /// Description of the source the pot was loaded from.
+    #[serde(skip)]
+    pub origin: String,
+    /// Directory in the support file directory the support files of the pot are copied to, set
+    /// for aliased pots so they do not collide with the files of other versions of the pot.
+    #[serde(skip)]
+    pub support_files_dir: Option<String>,
 }
 
 impl BonsaiPot {
@@ -101,52 +331,419 @@ impl BonsaiPot {
         Ok(serde_yaml::from_str(&contents)?)
     }
 
-    fn get_fn_name(&self, base_name: &str) -> String {
+    pub fn get_fn_name(&self, base_name: &str) -> String {
         format!("{}_{}", self.name, base_name)
     }
 
-    pub fn update_fn_map(&self, fn_map: &mut HashMap<String, FunctionDefinition>) -> bool {
-        let mut n_updates = 0;
-        for (fn_name, fn_def) in &self.functions {
-            if !fn_map.contains_key(&self.get_fn_name(fn_name)) {
-                let maybe_fn_cmds = fn_def.try_translate(fn_map);
-                if let Some(fn_cmds) = maybe_fn_cmds {
-                    n_updates += 1;
-                    fn_map.insert(self.get_fn_name(fn_name), fn_cmds);
+    /// Get the name used to call the given function from bonsai, `pot:function`.
+    pub fn get_call_name(&self, base_name: &str) -> String {
+        format!("{}:{}", self.name, base_name)
+    }
+
+    /// Get the call name (`pot:function`) and evergreen function name of each function.
+    pub fn get_call_names(&self) -> Vec<(String, String)> {
+        self.functions
+            .keys()
+            .map(|f| (self.get_call_name(f), self.get_fn_name(f)))
+            .collect()
+    }
+
+    /// Rename the pot, along with any calls its functions make to the renamed pots.
+    fn rename(&mut self, renames: &HashMap<String, String>) {
+        if let Some(new_name) = renames.get(&self.name) {
+            self.name = new_name.clone();
+        }
+
+        let functions = self.functions.values_mut().map(|f| &mut f.actions);
+        let tasks = self.tasks.values_mut().map(|t| &mut t.commands);
+        for commands in functions.chain(tasks) {
+            for action in commands.iter_mut() {
+                if let BonsaiCommand::Bonsai(bonsai_call) = action {
+                    let renamed = bonsai_call.parse().and_then(|(pot, function)| {
+                        renames
+                            .get(pot)
+                            .map(|new_pot| format!("{}:{}", new_pot, function))
+                    });
+                    if let Some(renamed) = renamed {
+                        bonsai_call.bonsai = renamed;
+                    }
                 }
             }
         }
-
-        n_updates != 0
     }
 }
 
+/// Check if a name can be used as an alias, aliases are used in calls and as a directory name.
+fn is_valid_alias(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains([':', '/', '\\'])
+}
+
 impl BonsaiPotDesc {
-    pub fn get_pots(&self) -> Result<Vec<BonsaiPot>, Box<dyn Error>> {
+    pub fn get_pots(&self, pot_loader: &mut PotLoader) -> Result<Vec<BonsaiPot>, Box<dyn Error>> {
         match &self.source {
             BonsaiPotSource::Local(local_source) => {
-                Ok(vec![BonsaiPot::from_path(Path::new(&local_source.path))?])
+                let path = Path::new(&local_source.path);
+                if path.is_dir() {
+                    read_manifest_pots(path)
+                } else {
+                    Ok(vec![BonsaiPot::from_path(path)?])
+                }
             }
-            BonsaiPotSource::Github(github) => get_remote_pots(github),
+            BonsaiPotSource::Github(github) => get_remote_pots(github, pot_loader),
+            BonsaiPotSource::Git(git) => get_remote_pots(git, pot_loader),
         }
     }
 
+    /// Describe where the pots of this source come from, `path` or `owner/repo@commit`.
+    pub fn describe(&self, pot_loader: &PotLoader) -> String {
+        let (remote, version) = match &self.source {
+            BonsaiPotSource::Local(local_source) => return local_source.path.clone(),
+            BonsaiPotSource::Github(github) => (github.get_remote_id(), github.get_version()),
+            BonsaiPotSource::Git(git) => (git.get_remote_id(), git.get_version()),
+        };
+
+        match pot_loader.resolved.find(&remote, version) {
+            Some(locked) => format!("{}@{}", remote, short_commit(&locked.commit)),
+            None => remote.to_string(),
+        }
+    }
+
+    /// Check if the pot with the given name, as defined by the source, is imported.
+    fn is_imported(&self, name: &str) -> bool {
+        self.pots
+            .as_ref()
+            .is_none_or(|pots| pots.iter().any(|p| p == name))
+    }
+
+    /// Get the names the pots of this source define for the given pots as they are imported.
+    pub fn get_source_names(&self, pot_names: &HashSet<String>) -> HashSet<String> {
+        let empty = HashMap::new();
+        let alias = self.alias.as_ref().unwrap_or(&empty);
+        let renamed: HashSet<&String> = alias.values().collect();
+
+        pot_names
+            .iter()
+            .filter(|name| !alias.contains_key(*name) && !renamed.contains(name))
+            .cloned()
+            .chain(
+                alias
+                    .iter()
+                    .filter(|(_, new_name)| pot_names.contains(*new_name))
+                    .map(|(name, _)| name.clone()),
+            )
+            .filter(|name| self.is_imported(name))
+            .collect()
+    }
+
+    /// Get the directory in the support file directory the support files of the given pot, as
+    /// defined by the source, are copied to.
+    pub fn get_support_files_dir(&self, name: &str) -> PathBuf {
+        self.alias
+            .as_ref()
+            .and_then(|alias| alias.get(name))
+            .map(PathBuf::from)
+            .unwrap_or_default()
+    }
+
+    /// Get the pots of this source that are used, as defined by the source, along with the
+    /// directory their support files are copied to.
+    pub fn get_used_pots(&self, pot_names: &HashSet<String>) -> HashMap<String, PathBuf> {
+        self.get_source_names(pot_names)
+            .into_iter()
+            .map(|name| {
+                let support_files_dir = self.get_support_files_dir(&name);
+                (name, support_files_dir)
+            })
+            .collect()
+    }
+
+    /// Load the pots imported from this source under their imported names.
+    fn load_pots(&self, pot_loader: &mut PotLoader) -> Result<Vec<BonsaiPot>, Box<dyn Error>> {
+        let pots = self.get_pots(pot_loader)?;
+        let origin = self.describe(pot_loader);
+
+        let requested = self.pots.iter().flatten();
+        let aliased = self.alias.iter().flat_map(|alias| alias.keys());
+        for name in requested.chain(aliased) {
+            if !pots.iter().any(|p| &p.name == name) {
+                bail!("Pot '{}' is not defined by this source", name);
+            }
+        }
+        if let Some(alias) = &self.alias {
+            if let Some(name) = alias.keys().find(|name| !self.is_imported(name)) {
+                bail!("Cannot alias pot '{}', it is not imported", name);
+            }
+            if let Some(new_name) = alias.values().find(|n| !is_valid_alias(n)) {
+                bail!(
+                    "Invalid alias '{}', aliases cannot be empty or contain ':' or '/'",
+                    new_name
+                );
+            }
+        }
+
+        let mut imported: Vec<BonsaiPot> = pots
+            .into_iter()
+            .filter(|pot| self.is_imported(&pot.name))
+            .collect();
+        for pot in imported.iter_mut() {
+            if let Some(alias) = &self.alias {
+                pot.support_files_dir = alias.get(&pot.name).cloned();
+                pot.rename(alias);
+            }
+            pot.origin = origin.clone();
+        }
+
+        Ok(imported)
+    }
+
+    /// Add the pots of this source, and of the sources they declare, to the given map.
+    ///
+    /// A pot with the same name as one already in the map is reported as a conflict, unless it
+    /// was loaded from the same source.
     pub fn update_pot_map(
         &self,
         pot_map: &mut HashMap<String, BonsaiPot>,
+        pot_loader: &mut PotLoader,
+        errors: &mut BonsaiErrors,
     ) -> Result<(), Box<dyn Error>> {
-        let pots = self.get_pots()?;
+        let pots = self.load_pots(pot_loader)?;
         for pot in pots {
-            if !pot_map.contains_key(&pot.name) {
-                pot_map.insert(pot.name.clone(), pot.clone());
-                if let Some(child_pots) = &pot.bonsai {
-                    child_pots
-                        .iter()
-                        .try_for_each(|p| p.update_pot_map(pot_map))?;
+            if let Some(existing) = pot_map.get(&pot.name) {
+                if existing.origin != pot.origin {
+                    errors.push(
+                        format!(
+                            "Pot '{}' is defined by both '{}' and '{}', use `alias` to import one of them under another name",
+                            pot.name, existing.origin, pot.origin
+                        )
+                        .into(),
+                    );
                 }
+                continue;
+            }
+
+            pot_map.insert(pot.name.clone(), pot.clone());
+            if let Some(child_pots) = &pot.bonsai {
+                child_pots
+                    .iter()
+                    .try_for_each(|p| p.update_pot_map(pot_map, pot_loader, errors))?;
             }
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    const POT_A: &str = r#"
+name: a
+functions:
+  x:
+    description: x
+    actions:
+      - bonsai: b:y
+      - bonsai: other:z
+tasks:
+  t:
+    description: t
+    commands:
+      - bonsai: a:x
+"#;
+
+    const POT_B: &str = r#"
+name: b
+functions:
+  y:
+    description: y
+    actions:
+      - bonsai: a:x
+"#;
+
+    /// Write a directory with a manifest listing pots `a` and `b`.
+    fn create_pots_dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(format!("evg-bonsai-{}-{}", name, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        write(
+            dir.join("bonsai.manifest.yml"),
+            "bonsai_pots:\n  - {name: a, path: a.yml, description: a}\n  - {name: b, path: b.yml, description: b}\n",
+        )
+        .unwrap();
+        write(dir.join("a.yml"), POT_A).unwrap();
+        write(dir.join("b.yml"), POT_B).unwrap();
+        dir
+    }
+
+    fn descriptor(path: &Path, options: &str) -> BonsaiPotDesc {
+        serde_yaml::from_str(&format!(
+            "{{source: local, path: '{}', {}}}",
+            path.display(),
+            options
+        ))
+        .unwrap()
+    }
+
+    fn calls(commands: &[BonsaiCommand]) -> Vec<String> {
+        commands
+            .iter()
+            .filter_map(|c| match c {
+                BonsaiCommand::Bonsai(call) => Some(call.bonsai.clone()),
+                BonsaiCommand::EvergreenNative(_) => None,
+            })
+            .collect()
+    }
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut pot: BonsaiPot = serde_yaml::from_str(POT_A).unwrap();
+        let renames = vec![("a".to_string(), "a2".to_string())]
+            .into_iter()
+            .collect();
+
+        pot.rename(&renames);
+
+        assert_eq!(pot.name, "a2");
+        assert_eq!(calls(&pot.functions["x"].actions), vec!["b:y", "other:z"]);
+        assert_eq!(calls(&pot.tasks["t"].commands), vec!["a2:x"]);
+    }
+
+    #[test]
+    fn test_get_source_names() {
+        let desc = descriptor(Path::new("pots"), "pots: [a, b], alias: {a: a2}");
+
+        assert_eq!(
+            desc.get_source_names(&names(&["a", "a2", "b", "c"])),
+            names(&["a", "b"])
+        );
+        assert_eq!(desc.get_source_names(&names(&["b"])), names(&["b"]));
+        assert_eq!(desc.get_support_files_dir("a"), PathBuf::from("a2"));
+        assert_eq!(desc.get_support_files_dir("b"), PathBuf::new());
+    }
+
+    #[test]
+    fn test_load_pots_with_alias() {
+        let dir = create_pots_dir("load-pots-alias");
+        let mut pot_loader = PotLoader::default();
+
+        let desc = descriptor(&dir, "alias: {a: a2}");
+        let mut pots = desc.load_pots(&mut pot_loader).unwrap();
+        pots.sort_by(|p, q| p.name.cmp(&q.name));
+
+        assert_eq!(pots.len(), 2);
+        assert_eq!(pots[0].name, "a2");
+        assert_eq!(pots[0].support_files_dir, Some("a2".to_string()));
+        assert_eq!(calls(&pots[0].tasks["t"].commands), vec!["a2:x"]);
+        assert_eq!(pots[1].name, "b");
+        assert_eq!(pots[1].support_files_dir, None);
+        assert_eq!(calls(&pots[1].functions["y"].actions), vec!["a2:x"]);
+        assert_eq!(pots[1].origin, dir.display().to_string());
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_pots_selects_pots() {
+        let dir = create_pots_dir("load-pots-select");
+        let mut pot_loader = PotLoader::default();
+
+        let pots = descriptor(&dir, "pots: [b]")
+            .load_pots(&mut pot_loader)
+            .unwrap();
+        assert_eq!(pots.len(), 1);
+        assert_eq!(pots[0].name, "b");
+
+        for options in [
+            "pots: [c]",
+            "pots: [b], alias: {a: a2}",
+            "alias: {c: c2}",
+            "alias: {a: '../a'}",
+            "alias: {a: 'x:y'}",
+        ] {
+            assert!(
+                descriptor(&dir, options)
+                    .load_pots(&mut pot_loader)
+                    .is_err(),
+                "{}",
+                options
+            );
+        }
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_url() {
+        let cases = [
+            (
+                "https://github.com/owner/repo.git",
+                ("github.com", "owner/repo.git"),
+            ),
+            (
+                "https://user@example.com:8443/a/b",
+                ("example.com:8443", "a/b"),
+            ),
+            ("ssh://git@example.com/a/b.git", ("example.com", "a/b.git")),
+            (
+                "git@github.com:owner/repo.git",
+                ("github.com", "owner/repo.git"),
+            ),
+            ("file:///tmp/repos/pots", ("local", "tmp/repos/pots")),
+            ("/tmp/repos/pots", ("local", "/tmp/repos/pots")),
+            ("./repos/pots", ("local", "./repos/pots")),
+        ];
+
+        for (url, (host, path)) in cases.iter() {
+            assert_eq!(
+                split_url(url),
+                (host.to_string(), path.to_string()),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_cache_path_is_the_same_for_every_transport() {
+        let github = RemoteId::Github {
+            owner: "owner".to_string(),
+            repo: "repo".to_string(),
+        };
+        let https = RemoteId::Git {
+            url: "https://github.com/owner/repo.git".to_string(),
+        };
+        let ssh = RemoteId::Git {
+            url: "git@github.com:owner/repo.git".to_string(),
+        };
+
+        let expected = PathBuf::from("github.com/owner/repo");
+        assert_eq!(github.get_cache_path(), expected);
+        assert_eq!(https.get_cache_path(), expected);
+        assert_eq!(ssh.get_cache_path(), expected);
+    }
+
+    #[test]
+    fn test_get_cache_path_stays_inside_the_cache() {
+        let remote = RemoteId::Git {
+            url: "https://example.com:8443/../../etc/./repo.git".to_string(),
+        };
+        assert_eq!(
+            remote.get_cache_path(),
+            PathBuf::from("example.com_8443/etc/repo")
+        );
+
+        let local = RemoteId::Git {
+            url: "/tmp/repos/pots".to_string(),
+        };
+        assert_eq!(
+            local.get_cache_path(),
+            PathBuf::from("local/tmp/repos/pots")
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::errors::BonsaiErrors;
+use crate::landscape::command::{BonsaiCommand, CallSite};
+use crate::landscape::pot::BonsaiPot;
+use crate::pot::manifest::is_included;
+use serde_yaml::Value;
+use shrub_rs::models::commands::EvgCommand;
+use simple_error::bail;
+use std::error::Error;
+use std::path::Path;
+
+/// Name of the placeholder function referring to a support file, `{{ bonsai_file("path") }}`.
+const PLACEHOLDER_FN: &str = "bonsai_file";
+const PLACEHOLDER_START: &str = "{{";
+const PLACEHOLDER_END: &str = "}}";
+
+/// Function giving the path a support file placeholder is replaced with.
+type ResolveFn<'a> = dyn FnMut(&str) -> Result<String, Box<dyn Error>> + 'a;
+
+/// Get the path of the support file referred to by the contents of a placeholder, if any.
+fn parse_placeholder(contents: &str) -> Option<&str> {
+    let argument = contents
+        .trim()
+        .strip_prefix(PLACEHOLDER_FN)?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .trim();
+
+    ['"', '\''].iter().find_map(|quote| {
+        argument
+            .strip_prefix(*quote)
+            .and_then(|a| a.strip_suffix(*quote))
+    })
+}
+
+/// Replace every support file placeholder in the given string.
+///
+/// Returns `None` if the string does not contain any placeholders.
+fn replace_placeholders(
+    value: &str,
+    resolve: &mut ResolveFn,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut replaced = String::new();
+    let mut found = false;
+    let mut rest = value;
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let after_start = &rest[start + PLACEHOLDER_START.len()..];
+        let end = match after_start.find(PLACEHOLDER_END) {
+            Some(end) => end,
+            None => break,
+        };
+
+        replaced.push_str(&rest[..start]);
+        match parse_placeholder(&after_start[..end]) {
+            Some(path) => {
+                replaced.push_str(&resolve(path)?);
+                found = true;
+            }
+            None => replaced.push_str(
+                &rest[start..start + PLACEHOLDER_START.len() + end + PLACEHOLDER_END.len()],
+            ),
+        }
+        rest = &after_start[end + PLACEHOLDER_END.len()..];
+    }
+    replaced.push_str(rest);
+
+    Ok(if found { Some(replaced) } else { None })
+}
+
+/// Replace support file placeholders in every string of a YAML value.
+///
+/// Returns whether anything was replaced.
+fn replace_value_placeholders(
+    value: &mut Value,
+    resolve: &mut ResolveFn,
+) -> Result<bool, Box<dyn Error>> {
+    match value {
+        Value::String(string) => match replace_placeholders(string, resolve)? {
+            Some(replaced) => {
+                *string = replaced;
+                Ok(true)
+            }
+            None => Ok(false),
+        },
+        Value::Sequence(sequence) => sequence.iter_mut().try_fold(false, |changed, v| {
+            Ok(replace_value_placeholders(v, resolve)? || changed)
+        }),
+        Value::Mapping(mapping) => mapping.iter_mut().try_fold(false, |changed, (_, v)| {
+            Ok(replace_value_placeholders(v, resolve)? || changed)
+        }),
+        _ => Ok(false),
+    }
+}
+
+fn replace_command_placeholders(
+    command: &mut EvgCommand,
+    resolve: &mut ResolveFn,
+) -> Result<(), Box<dyn Error>> {
+    let mut value = serde_yaml::to_value(&*command)?;
+    if replace_value_placeholders(&mut value, resolve)? {
+        *command = serde_yaml::from_value(value)?;
+    }
+
+    Ok(())
+}
+
+/// Replace `{{ bonsai_file("path") }}` placeholders in the actions and task templates of a pot
+/// with the path the support file is copied to, `support_files_path/path`, or
+/// `support_files_path/alias/path` for aliased pots.
+///
+/// The referenced file must be listed in the `include_files` of the pot.
+pub fn resolve_support_files(
+    pot: &mut BonsaiPot,
+    support_files_path: &str,
+    errors: &mut BonsaiErrors,
+) {
+    let include_files = pot.include_files.clone().unwrap_or_default();
+    let exclude_files = pot.exclude_files.clone().unwrap_or_default();
+    let pot_name = pot.name.clone();
+    let support_files_path = match &pot.support_files_dir {
+        Some(dir) => format!("{}/{}", support_files_path, dir),
+        None => support_files_path.to_string(),
+    };
+
+    let mut resolve = |path: &str| -> Result<String, Box<dyn Error>> {
+        let path = path.trim_start_matches("./");
+        if !is_included(Path::new(path), &include_files, &exclude_files)? {
+            bail!(
+                "Support file '{}' is not listed in the include_files of pot '{}'",
+                path,
+                pot_name
+            );
+        }
+        Ok(format!("{}/{}", support_files_path, path))
+    };
+
+    let functions = pot.functions.iter_mut().map(|(name, f)| {
+        let call_site = CallSite::PotFunction(format!("{}:{}", pot_name, name));
+        (call_site, &mut f.actions)
+    });
+    let tasks = pot.tasks.iter_mut().map(|(name, t)| {
+        let call_site = CallSite::PotTask(format!("{}:{}", pot_name, name));
+        (call_site, &mut t.commands)
+    });
+    for (call_site, actions) in functions.chain(tasks) {
+        for action in actions.iter_mut() {
+            if let BonsaiCommand::EvergreenNative(command) = action {
+                if let Err(err) = replace_command_placeholders(command, &mut resolve) {
+                    errors.push(format!("{} in {}", err, call_site).into());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_placeholder() {
+        assert_eq!(
+            parse_placeholder(r#" bonsai_file("scripts/run.sh") "#),
+            Some("scripts/run.sh")
+        );
+        assert_eq!(
+            parse_placeholder("bonsai_file ( 'scripts/run.sh' )"),
+            Some("scripts/run.sh")
+        );
+        assert_eq!(parse_placeholder(r#"bonsai_file("scripts/run.sh')"#), None);
+        assert_eq!(parse_placeholder("bonsai_file(scripts/run.sh)"), None);
+        assert_eq!(parse_placeholder(r#"bonsai_files("scripts/run.sh")"#), None);
+        assert_eq!(parse_placeholder(" other "), None);
+    }
+
+    #[test]
+    fn test_replace_placeholders() {
+        let mut resolve = |path: &str| Ok(format!("src/bonsai_files/{}", path));
+
+        assert_eq!(
+            replace_placeholders(
+                r#"bash {{ bonsai_file("a.sh") }} {{ other }} {{bonsai_file('b.sh')}}"#,
+                &mut resolve
+            )
+            .unwrap(),
+            Some("bash src/bonsai_files/a.sh {{ other }} src/bonsai_files/b.sh".to_string())
+        );
+        assert_eq!(
+            replace_placeholders("echo ${expansion} {{ other }}", &mut resolve).unwrap(),
+            None
+        );
+    }
+}
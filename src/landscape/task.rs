@@ -1,11 +1,53 @@
-use crate::landscape::command::BonsaiCommand;
-use serde::{Deserialize, Serialize};
+use crate::landscape::command::{BonsaiCall, BonsaiCommand};
+use crate::landscape::param::{substitute_command, with_defaults};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_yaml::Value;
+use shrub_rs::models::params::ParamValue;
 use shrub_rs::models::task::{EvgTask, TaskDependency};
+use std::collections::HashMap;
 
-use super::command::BonsaiTranslator;
+use super::command::{BonsaiTranslator, CallSite};
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum BonsaiTask {
+    Definition(BonsaiTaskDefinition),
+    /// A task created from a task template of a pot.
+    Template(BonsaiTaskCall),
+}
+
+impl<'de> Deserialize<'de> for BonsaiTask {
+    /// Tasks that give a `bonsai_task` are created from a template, all others are defined in
+    /// full. Picking the variant by key keeps the errors of the fields of the variant.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let is_template = value
+            .as_mapping()
+            .is_some_and(|mapping| mapping.contains_key(&Value::from("bonsai_task")));
+        if is_template {
+            BonsaiTaskCall::deserialize(value)
+                .map(BonsaiTask::Template)
+                .map_err(D::Error::custom)
+        } else {
+            BonsaiTaskDefinition::deserialize(value)
+                .map(BonsaiTask::Definition)
+                .map_err(D::Error::custom)
+        }
+    }
+}
+
+impl BonsaiTask {
+    pub fn to_evg_task(&self, bonsai_translator: &mut BonsaiTranslator) -> EvgTask {
+        match self {
+            BonsaiTask::Definition(definition) => definition.to_evg_task(bonsai_translator),
+            BonsaiTask::Template(task_call) => task_call.to_evg_task(bonsai_translator),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct BonsaiTask {
+pub struct BonsaiTaskDefinition {
     /// Name of task being defined.
     pub name: String,
     /// List of command that make up the task.
@@ -27,9 +69,10 @@ pub struct BonsaiTask {
     pub stepback: Option<bool>,
 }
 
-impl BonsaiTask {
+impl BonsaiTaskDefinition {
     pub fn to_evg_task(&self, bonsai_translator: &mut BonsaiTranslator) -> EvgTask {
-        let command_list = bonsai_translator.translate_command_list(&self.commands);
+        let command_list = bonsai_translator
+            .translate_command_list(&self.commands, &CallSite::Task(self.name.clone()));
 
         EvgTask {
             name: self.name.clone(),
@@ -42,3 +85,190 @@ impl BonsaiTask {
         }
     }
 }
+
+/// Description of a task to create from a task template of a pot.
+///
+/// Any of the task settings given override the settings of the template.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BonsaiTaskCall {
+    /// Task template to create the task from, `pot:task`.
+    pub bonsai_task: String,
+    /// Name of the task, `pot_task` if not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Values of the params of the template.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub params: Option<HashMap<String, ParamValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depends_on: Option<Vec<TaskDependency>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patchable: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stepback: Option<bool>,
+}
+
+impl BonsaiTaskCall {
+    fn to_call(&self) -> BonsaiCall {
+        BonsaiCall {
+            bonsai: self.bonsai_task.clone(),
+            params: self.params.clone(),
+            substitute_params: None,
+        }
+    }
+
+    pub fn to_evg_task(&self, bonsai_translator: &mut BonsaiTranslator) -> EvgTask {
+        let task_call = self.to_call();
+        let name = self.name.clone().unwrap_or_else(|| task_call.get_fn_name());
+        let call_site = CallSite::Task(name.clone());
+
+        let template = match bonsai_translator.find_task_template(&task_call, &call_site) {
+            Some(template) => template,
+            None => {
+                return EvgTask {
+                    name,
+                    ..Default::default()
+                }
+            }
+        };
+
+        let task_call = match &template.params {
+            Some(params) => with_defaults(&task_call, params),
+            None => task_call,
+        };
+        let params = task_call.params.unwrap_or_default();
+        let mut commands = vec![];
+        for command in &template.commands {
+            match substitute_command(command, &params) {
+                Ok(command) => commands.push(command),
+                Err(err) => bonsai_translator
+                    .errors
+                    .push(format!("{} in {}", err, call_site).into()),
+            }
+        }
+
+        EvgTask {
+            name,
+            commands: bonsai_translator.translate_command_list(&commands, &call_site),
+            depends_on: self.depends_on.clone().or(template.depends_on),
+            exec_timeout_secs: self.exec_timeout_secs.or(template.exec_timeout_secs),
+            tags: self.tags.clone().or(template.tags),
+            patchable: self.patchable.or(template.patchable),
+            stepback: self.stepback.or(template.stepback),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shrub_rs::models::commands::{EvgCommand, FunctionCall};
+
+    const TEMPLATE: &str = r#"
+description: Lint the code.
+params:
+  - name: lang
+    description: Language to lint.
+    default: python
+commands:
+  - command: shell.exec
+    params:
+      script: "lint ${lang} ${other}"
+  - bonsai: lint:run
+    params:
+      target: "${lang}"
+exec_timeout_secs: 600
+tags: [lint]
+"#;
+
+    fn translator() -> BonsaiTranslator {
+        let mut translator = BonsaiTranslator::new();
+        translator.task_templates.insert(
+            "lint:check".to_string(),
+            serde_yaml::from_str(TEMPLATE).unwrap(),
+        );
+        translator.known_functions.insert("lint:run".to_string());
+        translator
+    }
+
+    fn to_evg_task(task: &str, translator: &mut BonsaiTranslator) -> EvgTask {
+        let task: BonsaiTask = serde_yaml::from_str(task).unwrap();
+        task.to_evg_task(translator)
+    }
+
+    #[test]
+    fn test_template_is_expanded_with_params() {
+        let mut translator = translator();
+        let task = to_evg_task(
+            "{bonsai_task: 'lint:check', params: {lang: rust}}",
+            &mut translator,
+        );
+
+        assert!(translator.errors.is_empty(), "{}", translator.errors);
+        assert_eq!(task.name, "lint_check");
+        let script = serde_yaml::to_string(&task.commands[0]).unwrap();
+        assert!(script.contains("lint rust ${other}"), "{}", script);
+        match &task.commands[1] {
+            EvgCommand::Function(FunctionCall { func, vars, .. }) => {
+                assert_eq!(func, "lint_run");
+                let vars = vars.as_ref().unwrap();
+                assert_eq!(vars["target"].to_string(), "rust");
+            }
+            command => panic!("Expected a function call, got {:?}", command),
+        }
+        assert!(translator.seen_pots.contains("lint"));
+    }
+
+    #[test]
+    fn test_template_settings_are_overridden() {
+        let mut translator = translator();
+        let task = to_evg_task(
+            "{bonsai_task: 'lint:check', name: lint_all, tags: [slow], patchable: false}",
+            &mut translator,
+        );
+
+        assert!(translator.errors.is_empty(), "{}", translator.errors);
+        assert_eq!(task.name, "lint_all");
+        assert_eq!(task.tags, Some(vec!["slow".to_string()]));
+        assert_eq!(task.patchable, Some(false));
+        assert_eq!(task.exec_timeout_secs, Some(600));
+        let script = serde_yaml::to_string(&task.commands[0]).unwrap();
+        assert!(script.contains("lint python"), "{}", script);
+    }
+
+    #[test]
+    fn test_unknown_template_is_reported() {
+        let mut translator = translator();
+        let task = to_evg_task("{bonsai_task: 'lint:chek'}", &mut translator);
+
+        assert_eq!(task.name, "lint_chek");
+        assert!(task.commands.is_empty());
+        assert_eq!(
+            translator.errors.to_string(),
+            "Unknown pot task 'lint:chek' in task 'lint_chek', did you mean 'lint:check'?"
+        );
+    }
+
+    #[test]
+    fn test_task_variant_is_picked_by_bonsai_task() {
+        let definition: BonsaiTask =
+            serde_yaml::from_str("{name: t, commands: [{bonsai: 'lint:run'}]}").unwrap();
+        assert!(matches!(definition, BonsaiTask::Definition(_)));
+
+        let template: BonsaiTask = serde_yaml::from_str("{bonsai_task: 'lint:check'}").unwrap();
+        assert!(matches!(template, BonsaiTask::Template(_)));
+
+        let err = serde_yaml::from_str::<BonsaiTask>("{name: t, comands: []}").unwrap_err();
+        assert!(
+            err.to_string().contains("missing field `commands`"),
+            "{}",
+            err
+        );
+        let err =
+            serde_yaml::from_str::<BonsaiTask>("{bonsai_task: 'lint:check', tags: a}").unwrap_err();
+        assert!(err.to_string().contains("expected a sequence"), "{}", err);
+    }
+}
@@ -1,20 +1,42 @@
 use crate::landscape::command::BonsaiTranslator;
 use crate::landscape::landscape::BonsaiLandscape;
+use crate::pot::cache::{get_cache_entries, remove_cache_entries};
+use crate::pot::credentials::GitConfig;
+use crate::pot::loader::PotLoader;
+use crate::pot::lockfile::{BonsaiLock, LOCK_FILE};
+use crate::pot::update::find_updates;
+use crate::pot::vendor::{BonsaiVendor, VENDOR_FILE};
 use chrono::Utc;
+use serde_yaml::Value;
+use shrub_rs::models::project::EvgProject;
+use similar::TextDiff;
+use simple_error::bail;
+use std::collections::BTreeSet;
+use std::env::{current_dir, temp_dir};
 use std::error::Error;
-use std::fs::{create_dir_all, read_to_string, File};
+use std::fs::{
+    create_dir_all, read, read_dir, read_to_string, remove_dir, remove_dir_all, remove_file,
+    rename, write, File,
+};
 use std::io::{BufWriter, Write};
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 use yaml_merge_keys::merge_keys;
 use yaml_rust::{YamlEmitter, YamlLoader};
 
+pub mod errors;
 pub mod landscape;
 pub mod pot;
 
 const SUPPORT_FILE_DIRECTORY: &str = "bonsai_files";
+/// File in the support file directory listing the files written by the last build.
+const GENERATED_FILES: &str = ".bonsai-generated.yml";
+const GENERATED_HEADER: &str = "# Generated from bonsai";
+const GENERATED_AT: &str = "# Generated at:";
+/// Keys in a generated project whose values come from unordered maps.
+const UNORDERED_KEYS: &[&str] = &["functions", "vars", "env", "environment_vars", "revisions"];
 
 fn get_merged_yaml(yaml_contents: &str) -> Result<String, Box<dyn Error>> {
-    let raw = YamlLoader::load_from_str(&yaml_contents)?.remove(0);
+    let raw = YamlLoader::load_from_str(yaml_contents)?.remove(0);
     let merged = merge_keys(raw)?;
 
     let mut out_str = String::new();
@@ -26,36 +48,715 @@ fn get_merged_yaml(yaml_contents: &str) -> Result<String, Box<dyn Error>> {
     Ok(out_str)
 }
 
+fn load_landscape(source_file: &Path) -> Result<BonsaiLandscape, Box<dyn Error>> {
+    let contents = read_to_string(source_file)?;
+    let merged_contents = get_merged_yaml(&contents)?;
+    Ok(serde_yaml::from_str(&merged_contents)?)
+}
+
+/// Get the path of the lock file for the given landscape, it lives next to the landscape.
+fn get_lock_file(source_file: &Path) -> PathBuf {
+    source_file.with_file_name(LOCK_FILE)
+}
+
+/// Create a loader for the remote pot sources of the given landscape.
+///
+/// If a vendor directory is given, all remote sources are loaded from the copies in it.
+fn create_pot_loader(
+    source_file: &Path,
+    git_config: GitConfig,
+    vendor_dir: Option<&Path>,
+) -> Result<PotLoader, Box<dyn Error>> {
+    let pot_loader = PotLoader::new(
+        BonsaiLock::from_path(&get_lock_file(source_file))?,
+        git_config,
+    );
+    Ok(match vendor_dir {
+        Some(vendor_dir) => pot_loader.with_vendor(BonsaiVendor::from_dir(vendor_dir)?),
+        None => pot_loader,
+    })
+}
+
+/// Get the path support files are copied to, relative to the working directory of a task.
+///
+/// The project is checked out to `checkout_dir` in the working directory and the configuration
+/// is generated to `target_dir`, relative to `project_dir`, the root of the project. An absolute
+/// `target_dir` must be inside `project_dir`.
+fn get_support_files_path(
+    checkout_dir: &str,
+    target_dir: &Path,
+    project_dir: &Path,
+) -> Result<String, Box<dyn Error>> {
+    let target_dir = if target_dir.is_absolute() {
+        match target_dir.strip_prefix(project_dir) {
+            Ok(relative) => relative,
+            Err(_) => bail!(
+                "Target directory '{}' is not inside the project directory '{}'",
+                target_dir.display(),
+                project_dir.display()
+            ),
+        }
+    } else {
+        target_dir
+    };
+
+    let mut segments = vec![checkout_dir.trim_end_matches('/').to_string()];
+    segments.extend(target_dir.components().filter_map(|c| match c {
+        Component::Normal(segment) => Some(segment.to_string_lossy().to_string()),
+        Component::ParentDir => Some("..".to_string()),
+        _ => None,
+    }));
+    segments.push(SUPPORT_FILE_DIRECTORY.to_string());
+    segments.retain(|s| !s.is_empty());
+    Ok(segments.join("/"))
+}
+
+/// Resolve the given landscape and report any problems found without writing any output.
+///
+/// This includes finding the support files of the pots used, which are only copied to a
+/// temporary directory.
+pub fn validate_landscape(
+    source_file: &Path,
+    git_config: GitConfig,
+    vendor_dir: Option<&Path>,
+    checkout_dir: &str,
+) -> Result<(), Box<dyn Error>> {
+    let bonsai_project = load_landscape(source_file)?;
+    let mut bonsai_translator = BonsaiTranslator::new().with_support_files_path(
+        &get_support_files_path(checkout_dir, Path::new("."), &current_dir()?)?,
+    );
+    let mut pot_loader = create_pot_loader(source_file, git_config, vendor_dir)?;
+    bonsai_project.create_evg_project(&mut bonsai_translator, &mut pot_loader)?;
+
+    // Support files are copied to a scratch directory, so missing files are found as in a build.
+    let scratch_dir = temp_dir().join(format!("evg-bonsai-support-files-{}", std::process::id()));
+    if scratch_dir.exists() {
+        remove_dir_all(&scratch_dir)?;
+    }
+    let copied =
+        bonsai_project.copy_support_files(&scratch_dir, &bonsai_translator, &mut pot_loader);
+    if scratch_dir.exists() {
+        remove_dir_all(&scratch_dir)?;
+    }
+    copied?;
+
+    Ok(())
+}
+
+/// Re-resolve the remote pot sources of the given landscape and update its lock file.
+///
+/// Only the given repositories (`owner/repo`) are updated, or all of them if none are given. A
+/// summary of the sources that moved to a different commit is printed.
+pub fn update_landscape(
+    source_file: &Path,
+    repos: Vec<String>,
+    git_config: GitConfig,
+) -> Result<(), Box<dyn Error>> {
+    let bonsai_project = load_landscape(source_file)?;
+    let lock_file = get_lock_file(source_file);
+    let previous_lock = BonsaiLock::from_path(&lock_file)?;
+    if git_config.offline {
+        bail!("Pot sources cannot be updated in offline mode");
+    }
+    let mut pot_loader =
+        PotLoader::new(previous_lock.clone(), git_config).with_updates(repos.clone());
+    bonsai_project.resolve_sources(&mut pot_loader)?;
+
+    for repo in &repos {
+        let is_source = pot_loader
+            .resolved
+            .sources
+            .iter()
+            .any(|s| &s.remote.to_string() == repo);
+        if !is_source {
+            bail!(
+                "'{}' is not a pot source of {}",
+                repo,
+                source_file.display()
+            );
+        }
+    }
+
+    let updates = find_updates(&previous_lock, &pot_loader)?;
+    pot_loader.resolved.write(&lock_file)?;
+
+    if updates.is_empty() {
+        println!("All pot sources are up to date.");
+    }
+    for update in updates {
+        println!("{}", update);
+    }
+
+    Ok(())
+}
+
+/// Render an evergreen project to YAML.
+///
+/// Functions and other maps are written in sorted order so that regenerating an unchanged
+/// landscape produces identical output.
+fn render_project(evergreen_project: &EvgProject) -> Result<String, Box<dyn Error>> {
+    let mut project_value = serde_yaml::to_value(evergreen_project)?;
+    sort_unordered_maps(&mut project_value, None);
+    Ok(serde_yaml::to_string(&project_value)?)
+}
+
+fn sort_unordered_maps(value: &mut Value, key: Option<&str>) {
+    match value {
+        Value::Mapping(mapping) => {
+            if let Some(key) = key {
+                if UNORDERED_KEYS.contains(&key) {
+                    let mut entries: Vec<(Value, Value)> =
+                        std::mem::take(mapping).into_iter().collect();
+                    entries.sort_by(|(a, _), (b, _)| a.as_str().cmp(&b.as_str()));
+                    mapping.extend(entries);
+                }
+            }
+            for (k, v) in mapping.iter_mut() {
+                sort_unordered_maps(v, k.as_str());
+            }
+        }
+        Value::Sequence(sequence) => sequence
+            .iter_mut()
+            .for_each(|v| sort_unordered_maps(v, None)),
+        _ => (),
+    }
+}
+
+/// Remove the header lines that change on every build from generated content.
+fn strip_generated_header(contents: &str) -> String {
+    contents
+        .lines()
+        .filter(|line| !line.starts_with(GENERATED_HEADER) && !line.starts_with(GENERATED_AT))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Compare the generated configuration against the existing target file.
+///
+/// A unified diff is printed and an error returned if they do not match.
+fn check_target_file(target_file: &Path, project_config: &str) -> Result<(), Box<dyn Error>> {
+    if !target_file.exists() {
+        bail!(
+            "{} does not exist, generate it with `evg-bonsai build`",
+            target_file.display()
+        );
+    }
+
+    let existing = strip_generated_header(&read_to_string(target_file)?);
+    let generated = strip_generated_header(project_config);
+    if existing == generated {
+        return Ok(());
+    }
+
+    let target_name = target_file.display().to_string();
+    let diff = TextDiff::from_lines(&existing, &generated);
+    print!(
+        "{}",
+        diff.unified_diff()
+            .header(&target_name, &format!("{} (generated)", target_name))
+    );
+
+    bail!(
+        "{} is out of date, regenerate it with `evg-bonsai build`",
+        target_file.display()
+    )
+}
+
+/// Fetch all remote pot sources of the given landscape into the cache.
+///
+/// Sources pinned in the lock file are fetched at their locked commit, so that the landscape
+/// can be built offline afterwards.
+pub fn fetch_landscape(source_file: &Path, git_config: GitConfig) -> Result<(), Box<dyn Error>> {
+    if git_config.offline {
+        bail!("Pot sources cannot be fetched in offline mode");
+    }
+    let bonsai_project = load_landscape(source_file)?;
+    let mut pot_loader = PotLoader::new(
+        BonsaiLock::from_path(&get_lock_file(source_file))?,
+        git_config,
+    );
+    bonsai_project.resolve_sources(&mut pot_loader)?;
+
+    for source in &pot_loader.resolved.sources {
+        println!("Fetched {} at {}", source.remote, source.commit);
+    }
+
+    Ok(())
+}
+
+/// Copy every remote pot source of the given landscape into a vendor directory.
+///
+/// Sources are copied at the commits they are locked to, and the lock file is written so the
+/// vendored copies and the lock agree. Sources vendored previously are removed first.
+pub fn vendor_landscape(
+    source_file: &Path,
+    vendor_dir: &Path,
+    git_config: GitConfig,
+) -> Result<(), Box<dyn Error>> {
+    let bonsai_project = load_landscape(source_file)?;
+    let lock_file = get_lock_file(source_file);
+    let mut pot_loader = PotLoader::new(BonsaiLock::from_path(&lock_file)?, git_config);
+    bonsai_project.resolve_sources(&mut pot_loader)?;
+
+    let mut vendor = if vendor_dir.join(VENDOR_FILE).exists() {
+        BonsaiVendor::from_dir(vendor_dir)?
+    } else {
+        BonsaiVendor {
+            dir: vendor_dir.to_path_buf(),
+            ..Default::default()
+        }
+    };
+    vendor.clear()?;
+    create_dir_all(vendor_dir)?;
+
+    for source in pot_loader.resolved.sources.clone() {
+        let repo_path = pot_loader.get_repository(&source)?;
+        vendor.add(&source, &repo_path)?;
+        println!("Vendored {} at {}", source.remote, source.commit);
+    }
+    vendor.write()?;
+    if !pot_loader.resolved.sources.is_empty() || lock_file.exists() {
+        pot_loader.resolved.write(&lock_file)?;
+    }
+
+    Ok(())
+}
+
+/// Print every repository in the cache of remote pots.
+pub fn list_cache(git_config: GitConfig) -> Result<(), Box<dyn Error>> {
+    let entries = get_cache_entries(&git_config)?;
+    if entries.is_empty() {
+        println!("The cache is empty.");
+    }
+    for entry in entries {
+        println!("{}", entry);
+    }
+
+    Ok(())
+}
+
+/// Remove repositories from the cache of remote pots.
+///
+/// Only repositories that have not been used for `older_than_days` are removed if given,
+/// otherwise everything is.
+pub fn clean_cache(
+    git_config: GitConfig,
+    older_than_days: Option<u32>,
+) -> Result<(), Box<dyn Error>> {
+    let removed = remove_cache_entries(&git_config, older_than_days)?;
+    for entry in &removed {
+        println!("Removed {}", entry.name);
+    }
+    println!("Removed {} cached repositories.", removed.len());
+
+    Ok(())
+}
+
+/// Check that a path is relative and made only of plain names, so it cannot refer to anything
+/// outside of the directory it is joined onto.
+pub(crate) fn is_plain_relative_path(path: &Path) -> bool {
+    path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Read the support files recorded as written by the previous build.
+///
+/// Only plain relative paths are accepted, so a modified record can never refer to files
+/// outside of the support file directory.
+fn read_generated_files(support_files_dir: &Path) -> Result<BTreeSet<PathBuf>, Box<dyn Error>> {
+    let generated_files = support_files_dir.join(GENERATED_FILES);
+    if !generated_files.exists() {
+        return Ok(BTreeSet::new());
+    }
+
+    let previous_files: BTreeSet<PathBuf> =
+        serde_yaml::from_str(&read_to_string(&generated_files)?)?;
+    for file in &previous_files {
+        if !is_plain_relative_path(file) {
+            bail!(
+                "Invalid path '{}' in {}, only paths inside the support file directory are allowed",
+                file.display(),
+                generated_files.display()
+            );
+        }
+    }
+    Ok(previous_files)
+}
+
+/// Check if two files have the same contents.
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (read(a), read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Move the support files copied to the staging directory into the support file directory.
+///
+/// Files that already exist but were not written by a previous build belong to the user and are
+/// never overwritten, an error is returned before anything is moved instead. Existing files
+/// identical to the ones being written, such as those left by builds from before generated files
+/// were recorded, are taken over.
+fn install_support_files(
+    staging_dir: &Path,
+    support_files_dir: &Path,
+    support_files: &BTreeSet<PathBuf>,
+    previous_files: &BTreeSet<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let conflicts: Vec<String> = support_files
+        .iter()
+        .filter(|file| !previous_files.contains(*file) && support_files_dir.join(file).exists())
+        .filter(|file| !is_same_file(&staging_dir.join(file), &support_files_dir.join(file)))
+        .map(|file| support_files_dir.join(file).display().to_string())
+        .collect();
+    if !conflicts.is_empty() {
+        bail!(
+            "Support files would overwrite files not generated by evg-bonsai: {}",
+            conflicts.join(", ")
+        );
+    }
+
+    for file in support_files {
+        let destination = support_files_dir.join(file);
+        if let Some(parent) = destination.parent() {
+            create_dir_all(parent)?;
+        }
+        rename(staging_dir.join(file), destination)?;
+    }
+
+    Ok(())
+}
+
+/// Remove support files written by a previous build that are no longer needed.
+///
+/// The files written by each build are recorded in the support file directory, only files
+/// recorded there are ever removed.
+fn remove_stale_support_files(
+    support_files_dir: &Path,
+    support_files: &BTreeSet<PathBuf>,
+    previous_files: &BTreeSet<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let generated_files = support_files_dir.join(GENERATED_FILES);
+    for stale_file in previous_files.difference(support_files) {
+        let stale_path = support_files_dir.join(stale_file);
+        if stale_path.is_file() {
+            remove_file(&stale_path)?;
+        }
+
+        // Remove any directories left empty, but never the support file directory itself.
+        for dir in stale_path.ancestors().skip(1) {
+            if dir == support_files_dir || !dir.starts_with(support_files_dir) {
+                break;
+            }
+            if read_dir(dir).map_or(true, |mut entries| entries.next().is_some()) {
+                break;
+            }
+            remove_dir(dir)?;
+        }
+    }
+
+    if !support_files.is_empty() {
+        create_dir_all(support_files_dir)?;
+        write(&generated_files, serde_yaml::to_string(support_files)?)?;
+    } else if generated_files.exists() {
+        remove_file(&generated_files)?;
+        if read_dir(support_files_dir)?.next().is_none() {
+            remove_dir(support_files_dir)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the given landscape into an evergreen configuration.
+///
+/// The commits remote pot sources resolve to are recorded in a lock file next to the landscape,
+/// and subsequent builds use those commits. If `check` is set, nothing is written. Instead, the
+/// generated configuration is compared to the existing target file and an error is returned if
+/// it is stale. If a vendor directory is given, remote pots are loaded from the copies in it
+/// instead of being fetched. References to support files in pots are resolved assuming the
+/// project is checked out to `checkout_dir` in the working directory of tasks.
 pub fn build_landscape(
     source_file: &Path,
     target_dir: &Path,
     target_filename: &str,
+    check: bool,
+    git_config: GitConfig,
+    vendor_dir: Option<&Path>,
+    checkout_dir: &str,
 ) -> Result<(), Box<dyn Error>> {
+    let bonsai_project = load_landscape(source_file)?;
+    let lock_file = get_lock_file(source_file);
+    let mut bonsai_translator = BonsaiTranslator::new().with_support_files_path(
+        &get_support_files_path(checkout_dir, target_dir, &current_dir()?)?,
+    );
+    let mut pot_loader = create_pot_loader(source_file, git_config, vendor_dir)?;
+    let evergreen_project =
+        bonsai_project.create_evg_project(&mut bonsai_translator, &mut pot_loader)?;
+    let project_config = render_project(&evergreen_project)?;
+
+    let mut target_file = target_dir.to_path_buf();
+    target_file.push(target_filename);
+
+    if check {
+        return check_target_file(&target_file, &project_config);
+    }
+
     if !target_dir.exists() {
         create_dir_all(target_dir)?;
     }
     let mut support_files_destination = target_dir.to_path_buf();
     support_files_destination.push(SUPPORT_FILE_DIRECTORY);
+    let previous_files = read_generated_files(&support_files_destination)?;
 
-    let contents = read_to_string(source_file)?;
-    let merged_contents = get_merged_yaml(&contents)?;
-    let bonsai_project: BonsaiLandscape = serde_yaml::from_str(&merged_contents)?;
-    let mut bonsai_translator = BonsaiTranslator::new();
-    let evergreen_project = bonsai_project.create_evg_project(&mut bonsai_translator)?;
-    bonsai_project
-        .copy_remote_support_files(support_files_destination.as_path(), &bonsai_translator)?;
+    // Support files are copied to a staging directory first, so that nothing is overwritten
+    // before it is known that none of them conflict with files of the user.
+    let staging_dir = support_files_destination.with_extension("partial");
+    if staging_dir.exists() {
+        remove_dir_all(&staging_dir)?;
+    }
+    let installed = bonsai_project
+        .copy_support_files(staging_dir.as_path(), &bonsai_translator, &mut pot_loader)
+        .and_then(|support_files| {
+            install_support_files(
+                &staging_dir,
+                &support_files_destination,
+                &support_files,
+                &previous_files,
+            )?;
+            Ok(support_files)
+        });
+    if staging_dir.exists() {
+        remove_dir_all(&staging_dir)?;
+    }
+    let support_files = installed?;
+    remove_stale_support_files(&support_files_destination, &support_files, &previous_files)?;
+    if !pot_loader.resolved.sources.is_empty() || lock_file.exists() {
+        pot_loader.resolved.write(&lock_file)?;
+    }
 
-    let project_config = serde_yaml::to_string(&evergreen_project)?;
     let now = Utc::now();
-
-    let mut target_file = target_dir.to_path_buf();
-    target_file.push(target_filename);
     let f = File::create(target_file)?;
     let mut writer = BufWriter::new(f);
 
-    writer.write_all("# Generated from bonsai\n".as_bytes())?;
-    writer.write_all(format!("# Generated at: {}\n", now).as_bytes())?;
+    writer.write_all(format!("{}\n", GENERATED_HEADER).as_bytes())?;
+    writer.write_all(format!("{} {}\n", GENERATED_AT, now).as_bytes())?;
     writer.write_all(project_config.as_bytes())?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory for a test, with the support file directory inside of it.
+    fn create_test_dir(name: &str) -> (PathBuf, PathBuf) {
+        let dir = temp_dir().join(format!("evg-bonsai-{}-{}", name, std::process::id()));
+        if dir.exists() {
+            remove_dir_all(&dir).unwrap();
+        }
+        let support_files_dir = dir.join(SUPPORT_FILE_DIRECTORY);
+        create_dir_all(&support_files_dir).unwrap();
+        (dir, support_files_dir)
+    }
+
+    fn write_file(path: &Path, contents: &str) {
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, contents).unwrap();
+    }
+
+    fn paths(paths: &[&str]) -> BTreeSet<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn test_get_support_files_path() {
+        let project_dir = Path::new("/work/project");
+        let cases = [
+            ("src", ".", "src/bonsai_files"),
+            ("src/", "evergreen", "src/evergreen/bonsai_files"),
+            ("", "./evergreen/", "evergreen/bonsai_files"),
+            ("src", "../shared", "src/../shared/bonsai_files"),
+            (
+                "src",
+                "/work/project/evergreen",
+                "src/evergreen/bonsai_files",
+            ),
+            ("src", "/work/project", "src/bonsai_files"),
+        ];
+
+        for (checkout_dir, target_dir, expected) in cases.iter() {
+            assert_eq!(
+                get_support_files_path(checkout_dir, Path::new(target_dir), project_dir).unwrap(),
+                *expected,
+                "{} {}",
+                checkout_dir,
+                target_dir
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_support_files_path_outside_project() {
+        let project_dir = Path::new("/work/project");
+
+        assert!(get_support_files_path("src", Path::new("/tmp/out"), project_dir).is_err());
+        assert!(
+            get_support_files_path("src", Path::new("/work/project-other"), project_dir).is_err()
+        );
+    }
+
+    #[test]
+    fn test_remove_stale_support_files() {
+        let (dir, support_files_dir) = create_test_dir("remove-stale");
+        write_file(&support_files_dir.join("old/nested/run.sh"), "old");
+        write_file(&support_files_dir.join("keep.sh"), "keep");
+        write_file(&support_files_dir.join("user.sh"), "user");
+
+        remove_stale_support_files(
+            &support_files_dir,
+            &paths(&["keep.sh"]),
+            &paths(&["old/nested/run.sh", "keep.sh"]),
+        )
+        .unwrap();
+
+        assert!(!support_files_dir.join("old").exists());
+        assert!(support_files_dir.join("keep.sh").exists());
+        assert!(support_files_dir.join("user.sh").exists());
+        assert_eq!(
+            read_generated_files(&support_files_dir).unwrap(),
+            paths(&["keep.sh"])
+        );
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_stale_support_files_stops_pruning_at_support_file_directory() {
+        let (dir, support_files_dir) = create_test_dir("remove-stale-prune");
+        write_file(&support_files_dir.join("a/b/run.sh"), "old");
+        write_file(&support_files_dir.join(GENERATED_FILES), "- a/b/run.sh\n");
+
+        let previous_files = read_generated_files(&support_files_dir).unwrap();
+        remove_stale_support_files(&support_files_dir, &paths(&["new.sh"]), &previous_files)
+            .unwrap();
+        assert!(!support_files_dir.join("a").exists());
+        assert!(support_files_dir.exists());
+
+        remove_stale_support_files(&support_files_dir, &BTreeSet::new(), &paths(&["new.sh"]))
+            .unwrap();
+        assert!(!support_files_dir.exists());
+        assert!(dir.exists());
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_support_files_refuses_to_overwrite_user_files() {
+        let (dir, support_files_dir) = create_test_dir("install-conflict");
+        let staging_dir = dir.join("staging");
+        write_file(&staging_dir.join("run.sh"), "generated");
+        write_file(&staging_dir.join("other.sh"), "generated");
+        write_file(&support_files_dir.join("run.sh"), "user");
+
+        let result = install_support_files(
+            &staging_dir,
+            &support_files_dir,
+            &paths(&["run.sh", "other.sh"]),
+            &BTreeSet::new(),
+        );
+
+        assert!(result.is_err());
+        assert_eq!(
+            read_to_string(support_files_dir.join("run.sh")).unwrap(),
+            "user"
+        );
+        assert!(!support_files_dir.join("other.sh").exists());
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_support_files_replaces_generated_and_adopts_identical_files() {
+        let (dir, support_files_dir) = create_test_dir("install-adopt");
+        let staging_dir = dir.join("staging");
+        write_file(&staging_dir.join("generated.sh"), "new");
+        write_file(&staging_dir.join("identical.sh"), "same");
+        write_file(&support_files_dir.join("generated.sh"), "old");
+        write_file(&support_files_dir.join("identical.sh"), "same");
+
+        install_support_files(
+            &staging_dir,
+            &support_files_dir,
+            &paths(&["generated.sh", "identical.sh"]),
+            &paths(&["generated.sh"]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_to_string(support_files_dir.join("generated.sh")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            read_to_string(support_files_dir.join("identical.sh")).unwrap(),
+            "same"
+        );
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_generated_files_rejects_tampered_records() {
+        let (dir, support_files_dir) = create_test_dir("read-generated");
+
+        for record in [
+            "- ../outside.sh\n",
+            "- /tmp/outside.sh\n",
+            "- a/../../b\n",
+            "- ./a\n",
+        ] {
+            write(support_files_dir.join(GENERATED_FILES), record).unwrap();
+            assert!(
+                read_generated_files(&support_files_dir).is_err(),
+                "{}",
+                record
+            );
+        }
+
+        write(support_files_dir.join(GENERATED_FILES), "- a/b.sh\n").unwrap();
+        assert_eq!(
+            read_generated_files(&support_files_dir).unwrap(),
+            paths(&["a/b.sh"])
+        );
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_landscape_finds_missing_support_files() {
+        let (dir, _) = create_test_dir("validate-landscape");
+        write_file(
+            &dir.join("pots/run.yml"),
+            "name: run\ninclude_files: [scripts/run.sh]\nfunctions:\n  run:\n    description: run\n    actions:\n      - command: shell.exec\n        params:\n          script: bash {{ bonsai_file(\"scripts/run.sh\") }}\n",
+        );
+        let source_file = dir.join("landscape.yml");
+        write_file(
+            &source_file,
+            &format!(
+                "bonsai:\n  - source: local\n    path: {}\nbuildvariants: []\ntasks:\n  - name: t\n    commands:\n      - bonsai: run:run\n",
+                dir.join("pots/run.yml").display()
+            ),
+        );
+
+        let validate = || validate_landscape(&source_file, GitConfig::default(), None, "src");
+        let err = validate().unwrap_err();
+        assert!(err.to_string().contains("scripts/run.sh"), "{}", err);
+
+        write_file(&dir.join("pots/scripts/run.sh"), "echo run");
+        validate().unwrap();
+        assert!(!dir.join(SUPPORT_FILE_DIRECTORY).join("scripts").exists());
+
+        remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,11 +1,26 @@
-use evg_bonsai::build_landscape;
+use evg_bonsai::pot::credentials::GitConfig;
+use evg_bonsai::{
+    build_landscape, clean_cache, fetch_landscape, list_cache, update_landscape,
+    validate_landscape, vendor_landscape,
+};
 use std::error::Error;
 use std::path::PathBuf;
+use std::process;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "evg-bonsai")]
-enum Opt {
+struct Opt {
+    /// Directory to cache remote pots in [default: evg-bonsai in the user's cache directory].
+    #[structopt(parse(from_os_str), long = "cache-dir", global = true)]
+    cache_dir: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
     /// Generate an Evergreen YAML configuration from a given bonsai source.
     Build {
         /// File to build evergreen configuration from.
@@ -19,16 +34,145 @@ enum Opt {
         /// Filename to use for generated output.
         #[structopt(long = "target-filename", default_value = "evergreen.yml")]
         target_filename: String,
+
+        /// Do not write anything, fail if the existing generated output is out of date.
+        #[structopt(long = "check")]
+        check: bool,
+
+        /// Only use remote pots that are already cached, never access the network.
+        #[structopt(long = "offline")]
+        offline: bool,
+
+        /// Load remote pots from the copies in this directory, created by `evg-bonsai vendor`.
+        #[structopt(parse(from_os_str), long = "vendor-dir")]
+        vendor_dir: Option<PathBuf>,
+
+        /// Directory the project is checked out to, relative to the working directory of tasks.
+        #[structopt(long = "checkout-dir", default_value = "src")]
+        checkout_dir: String,
+    },
+
+    /// Update the commits remote pot sources are locked to.
+    Update {
+        /// File to update the lock file of.
+        #[structopt(parse(from_os_str), long = "source-file")]
+        source_file: PathBuf,
+
+        /// Repositories to update, given as `owner/repo`. All are updated if none are given.
+        repos: Vec<String>,
     },
+
+    /// Check that a given bonsai source can be built without writing any output.
+    Validate {
+        /// File to validate.
+        #[structopt(parse(from_os_str), long = "source-file")]
+        source_file: PathBuf,
+
+        /// Only use remote pots that are already cached, never access the network.
+        #[structopt(long = "offline")]
+        offline: bool,
+
+        /// Load remote pots from the copies in this directory, created by `evg-bonsai vendor`.
+        #[structopt(parse(from_os_str), long = "vendor-dir")]
+        vendor_dir: Option<PathBuf>,
+
+        /// Directory the project is checked out to, relative to the working directory of tasks.
+        #[structopt(long = "checkout-dir", default_value = "src")]
+        checkout_dir: String,
+    },
+
+    /// Copy all remote pots of a landscape into a directory, so it can be built without them.
+    Vendor {
+        /// File to vendor the remote pots of.
+        #[structopt(parse(from_os_str), long = "source-file")]
+        source_file: PathBuf,
+
+        /// Directory to copy the remote pots to.
+        #[structopt(parse(from_os_str), long = "dir", default_value = "bonsai_vendor")]
+        dir: PathBuf,
+    },
+
+    /// Inspect and manage the cache of remote pots.
+    Cache(CacheCommand),
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let opt = Opt::from_args();
-    match opt {
-        Opt::Build {
+#[derive(Debug, StructOpt)]
+enum CacheCommand {
+    /// List the repositories in the cache.
+    List,
+
+    /// Remove repositories from the cache.
+    Clean {
+        /// Only remove repositories that have not been used for this many days.
+        #[structopt(long = "older-than")]
+        older_than: Option<u32>,
+    },
+
+    /// Fetch all remote pot sources of a landscape, so it can be built offline.
+    Fetch {
+        /// File to fetch the pot sources of.
+        #[structopt(parse(from_os_str), long = "source-file")]
+        source_file: PathBuf,
+    },
+}
+
+fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    let mut git_config = GitConfig::load()?;
+    if opt.cache_dir.is_some() {
+        git_config.cache_dir = opt.cache_dir;
+    }
+
+    match opt.command {
+        Command::Build {
             source_file,
             target_dir,
             target_filename,
-        } => build_landscape(&source_file, &target_dir, &target_filename),
+            check,
+            offline,
+            vendor_dir,
+            checkout_dir,
+        } => {
+            git_config.offline |= offline;
+            build_landscape(
+                &source_file,
+                &target_dir,
+                &target_filename,
+                check,
+                git_config,
+                vendor_dir.as_deref(),
+                &checkout_dir,
+            )
+        }
+        Command::Update { source_file, repos } => update_landscape(&source_file, repos, git_config),
+        Command::Validate {
+            source_file,
+            offline,
+            vendor_dir,
+            checkout_dir,
+        } => {
+            git_config.offline |= offline;
+            validate_landscape(
+                &source_file,
+                git_config,
+                vendor_dir.as_deref(),
+                &checkout_dir,
+            )?;
+            println!("{} is valid.", source_file.display());
+            Ok(())
+        }
+        Command::Vendor { source_file, dir } => vendor_landscape(&source_file, &dir, git_config),
+        Command::Cache(CacheCommand::List) => list_cache(git_config),
+        Command::Cache(CacheCommand::Clean { older_than }) => clean_cache(git_config, older_than),
+        Command::Cache(CacheCommand::Fetch { source_file }) => {
+            fetch_landscape(&source_file, git_config)
+        }
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    if let Err(err) = run(opt) {
+        eprintln!("Error: {}", err);
+        process::exit(1);
     }
 }
@@ -0,0 +1,286 @@
+use crate::pot::credentials::GitConfig;
+use crate::pot::github_service::{get_cache_dir, CHECKOUTS_DIR, REPOS_DIR};
+use crate::pot::update::short_commit;
+use chrono::{DateTime, Duration, Utc};
+use git2::Repository;
+use std::error::Error;
+use std::fmt;
+use std::fs::{read_dir, read_to_string, remove_dir_all, symlink_metadata, write};
+use std::path::{Path, PathBuf};
+
+/// File in a cached clone recording when it was last used.
+const LAST_USED_FILE: &str = "evg-bonsai-last-used";
+
+/// Record that a cached clone was just used.
+pub fn mark_used(repo_dir: &Path) -> Result<(), Box<dyn Error>> {
+    write(repo_dir.join(LAST_USED_FILE), Utc::now().to_rfc3339())?;
+    Ok(())
+}
+
+/// A cached clone of a remote repository along with its checkouts.
+pub struct CacheEntry {
+    /// Path of the clone relative to the cache, `host/owner/repo`.
+    pub name: String,
+    /// Url the repository was last fetched from.
+    pub url: Option<String>,
+    /// Commits that have been checked out.
+    pub commits: Vec<String>,
+    /// Size on disk of the clone and its checkouts, in bytes.
+    pub size: u64,
+    pub last_used: DateTime<Utc>,
+    repo_dir: PathBuf,
+    checkouts_dir: PathBuf,
+}
+
+impl CacheEntry {
+    fn new(cache_dir: &Path, repo_dir: PathBuf) -> Result<CacheEntry, Box<dyn Error>> {
+        let relative_path = repo_dir.strip_prefix(cache_dir.join(REPOS_DIR))?;
+        let checkouts_dir = cache_dir.join(CHECKOUTS_DIR).join(relative_path);
+
+        let repo = Repository::open(&repo_dir)?;
+        let url = repo
+            .find_remote("origin")
+            .ok()
+            .and_then(|remote| remote.url().map(|url| url.to_string()));
+
+        let mut commits = vec![];
+        if checkouts_dir.exists() {
+            for entry in read_dir(&checkouts_dir)? {
+                let path = entry?.path();
+                if path.is_dir() && path.extension().is_none() {
+                    if let Some(commit) = path.file_name().and_then(|name| name.to_str()) {
+                        commits.push(commit.to_string());
+                    }
+                }
+            }
+        }
+        commits.sort();
+
+        let last_used = match read_to_string(repo_dir.join(LAST_USED_FILE)) {
+            Ok(contents) => DateTime::parse_from_rfc3339(contents.trim())?.with_timezone(&Utc),
+            Err(_) => symlink_metadata(&repo_dir)?.modified()?.into(),
+        };
+
+        Ok(CacheEntry {
+            name: relative_path.display().to_string(),
+            url,
+            commits,
+            size: dir_size(&repo_dir)? + dir_size(&checkouts_dir)?,
+            last_used,
+            repo_dir,
+            checkouts_dir,
+        })
+    }
+
+    /// Delete the clone and all its checkouts.
+    pub fn remove(&self) -> Result<(), Box<dyn Error>> {
+        for dir in &[&self.repo_dir, &self.checkouts_dir] {
+            if dir.exists() {
+                remove_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+impl fmt::Display for CacheEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        if let Some(url) = &self.url {
+            writeln!(f, "    url: {}", url)?;
+        }
+        let commits: Vec<&str> = self.commits.iter().map(|c| short_commit(c)).collect();
+        writeln!(f, "    checkouts: {}", commits.join(", "))?;
+        writeln!(f, "    size: {}", format_size(self.size))?;
+        write!(
+            f,
+            "    last used: {}",
+            self.last_used.format("%Y-%m-%d %H:%M:%S UTC")
+        )
+    }
+}
+
+/// Get the total size of all files under the given directory.
+fn dir_size(dir: &Path) -> Result<u64, Box<dyn Error>> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let mut size = 0;
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let metadata = symlink_metadata(entry.path())?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Find the git repositories nested anywhere below the given directory.
+fn find_repositories(dir: &Path, repos: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        if Repository::open(&path).is_ok() {
+            repos.push(path);
+        } else {
+            find_repositories(&path, repos)?;
+        }
+    }
+    Ok(())
+}
+
+/// Get every repository in the cache, sorted by name.
+pub fn get_cache_entries(git_config: &GitConfig) -> Result<Vec<CacheEntry>, Box<dyn Error>> {
+    let cache_dir = get_cache_dir(git_config)?;
+    let repos_dir = cache_dir.join(REPOS_DIR);
+    let mut repos = vec![];
+    if repos_dir.exists() {
+        find_repositories(&repos_dir, &mut repos)?;
+    }
+
+    let mut entries = repos
+        .into_iter()
+        .map(|repo_dir| CacheEntry::new(&cache_dir, repo_dir))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Remove repositories from the cache, returning the entries that were removed.
+///
+/// If `older_than_days` is given, only repositories that have not been used for that many days
+/// are removed, otherwise the whole cache is cleared.
+pub fn remove_cache_entries(
+    git_config: &GitConfig,
+    older_than_days: Option<u32>,
+) -> Result<Vec<CacheEntry>, Box<dyn Error>> {
+    let entries = get_cache_entries(git_config)?;
+    let removed: Vec<CacheEntry> = match older_than_days {
+        Some(days) => {
+            let cutoff = Utc::now() - Duration::days(days.into());
+            entries
+                .into_iter()
+                .filter(|entry| entry.last_used < cutoff)
+                .collect()
+        }
+        None => entries,
+    };
+    for entry in &removed {
+        entry.remove()?;
+    }
+
+    if older_than_days.is_none() {
+        let cache_dir = get_cache_dir(git_config)?;
+        for dir in &[REPOS_DIR, CHECKOUTS_DIR] {
+            let dir = cache_dir.join(dir);
+            if dir.exists() {
+                remove_dir_all(dir)?;
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs::create_dir_all;
+
+    /// Create a cache with a clone of each of the given repositories and its checkouts.
+    fn create_test_cache(name: &str, repos: &[&str]) -> GitConfig {
+        let cache_dir = temp_dir().join(format!("evg-bonsai-{}-{}", name, std::process::id()));
+        if cache_dir.exists() {
+            remove_dir_all(&cache_dir).unwrap();
+        }
+        for repo in repos {
+            Repository::init(cache_dir.join(REPOS_DIR).join(repo)).unwrap();
+            create_dir_all(cache_dir.join(CHECKOUTS_DIR).join(repo).join("abc1234")).unwrap();
+        }
+        GitConfig {
+            cache_dir: Some(cache_dir),
+            ..Default::default()
+        }
+    }
+
+    fn entry_names(entries: &[CacheEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn test_remove_cache_entries_older_than() {
+        let git_config = create_test_cache("cache-older-than", &["host/a", "host/b"]);
+        let cache_dir = git_config.cache_dir.clone().unwrap();
+        let repos_dir = cache_dir.join(REPOS_DIR);
+        mark_used(&repos_dir.join("host/a")).unwrap();
+        let old = Utc::now() - Duration::days(45);
+        write(
+            repos_dir.join("host/b").join(LAST_USED_FILE),
+            old.to_rfc3339(),
+        )
+        .unwrap();
+
+        let entries = get_cache_entries(&git_config).unwrap();
+        assert_eq!(entry_names(&entries), vec!["host/a", "host/b"]);
+        assert_eq!(entries[0].commits, vec!["abc1234"]);
+
+        let removed = remove_cache_entries(&git_config, Some(60)).unwrap();
+        assert!(removed.is_empty());
+
+        let removed = remove_cache_entries(&git_config, Some(30)).unwrap();
+        assert_eq!(entry_names(&removed), vec!["host/b"]);
+        assert!(!repos_dir.join("host/b").exists());
+        assert!(!cache_dir.join(CHECKOUTS_DIR).join("host/b").exists());
+        assert!(cache_dir.join(CHECKOUTS_DIR).join("host/a").exists());
+
+        let entries = get_cache_entries(&git_config).unwrap();
+        assert_eq!(entry_names(&entries), vec!["host/a"]);
+
+        remove_dir_all(cache_dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_cache_entries_clears_cache() {
+        let git_config = create_test_cache("cache-clear", &["host/a", "host/b"]);
+        let cache_dir = git_config.cache_dir.clone().unwrap();
+        mark_used(&cache_dir.join(REPOS_DIR).join("host/a")).unwrap();
+
+        let removed = remove_cache_entries(&git_config, None).unwrap();
+        assert_eq!(entry_names(&removed), vec!["host/a", "host/b"]);
+        assert!(!cache_dir.join(REPOS_DIR).exists());
+        assert!(!cache_dir.join(CHECKOUTS_DIR).exists());
+        assert!(get_cache_entries(&git_config).unwrap().is_empty());
+
+        remove_dir_all(cache_dir).unwrap();
+    }
+}
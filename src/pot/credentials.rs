@@ -0,0 +1,190 @@
+use crate::landscape::pot::split_url;
+use crate::pot::github_service::APPLICATION_NAME;
+use directories_next::BaseDirs;
+use git2::{Cred, CredentialType, ErrorClass, ErrorCode, RemoteCallbacks};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+const CONFIG_FILE: &str = "config.yml";
+const TRANSPORT_ENV_VAR: &str = "EVG_BONSAI_GIT_TRANSPORT";
+const OFFLINE_ENV_VAR: &str = "EVG_BONSAI_OFFLINE";
+const CACHE_DIR_ENV_VAR: &str = "EVG_BONSAI_CACHE_DIR";
+const TOKEN_ENV_VARS: &[&str] = &["EVG_BONSAI_GITHUB_TOKEN", "GITHUB_TOKEN"];
+/// SSH keys to try, in order, if no key is configured.
+const DEFAULT_SSH_KEYS: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+/// Username to authenticate with when using a token over https.
+const TOKEN_USERNAME: &str = "x-access-token";
+/// Host the token is offered to, it is never sent to any other server.
+const TOKEN_HOST: &str = "github.com";
+
+/// Protocol used to clone and fetch remote repositories.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GitTransport {
+    #[default]
+    Ssh,
+    Https,
+}
+
+impl GitTransport {
+    fn from_name(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name.to_lowercase().as_str() {
+            "ssh" => Ok(GitTransport::Ssh),
+            "https" => Ok(GitTransport::Https),
+            _ => Err(format!(
+                "Unknown git transport '{}', expected 'ssh' or 'https'",
+                name
+            )
+            .into()),
+        }
+    }
+}
+
+/// Settings used to access remote repositories.
+///
+/// Read from `evg-bonsai/config.yml` in the user's config directory, with environment variables
+/// taking precedence over the file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GitConfig {
+    /// Transport to use for sources that do not specify one.
+    #[serde(default)]
+    pub transport: GitTransport,
+    /// Token to authenticate with over https.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// SSH private key to authenticate with, the default keys in `~/.ssh` are tried if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<PathBuf>,
+    /// Only use repositories that are already in the cache, never access the network.
+    #[serde(default)]
+    pub offline: bool,
+    /// Directory to cache remote repositories in, the user's cache directory is used if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl GitConfig {
+    pub fn load() -> Result<GitConfig, Box<dyn Error>> {
+        let mut config = match get_config_file() {
+            Some(config_file) if config_file.exists() => {
+                let contents = read_to_string(&config_file)?;
+                serde_yaml::from_str(&contents)
+                    .map_err(|err| format!("Could not read {}: {}", config_file.display(), err))?
+            }
+            _ => GitConfig::default(),
+        };
+
+        if let Ok(transport) = env::var(TRANSPORT_ENV_VAR) {
+            config.transport = GitTransport::from_name(&transport)?;
+        }
+        if let Ok(offline) = env::var(OFFLINE_ENV_VAR) {
+            config.offline = !matches!(offline.to_lowercase().as_str(), "" | "0" | "false" | "no");
+        }
+        if let Ok(cache_dir) = env::var(CACHE_DIR_ENV_VAR) {
+            config.cache_dir = Some(PathBuf::from(cache_dir));
+        }
+        if let Some(token) = TOKEN_ENV_VARS.iter().find_map(|var| env::var(var).ok()) {
+            config.token = Some(token);
+        }
+
+        Ok(config)
+    }
+
+    fn ssh_keys(&self) -> Vec<PathBuf> {
+        if let Some(ssh_key) = &self.ssh_key {
+            return vec![ssh_key.clone()];
+        }
+
+        match BaseDirs::new() {
+            Some(base_dirs) => DEFAULT_SSH_KEYS
+                .iter()
+                .map(|key| base_dirs.home_dir().join(".ssh").join(key))
+                .filter(|key| key.exists())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    /// Create callbacks that provide credentials when a remote asks for them.
+    ///
+    /// Each kind of credential is only offered once, so authentication fails rather than
+    /// retrying forever when none of them are accepted. The token is only offered to github.
+    pub fn create_callbacks(&self) -> RemoteCallbacks<'static> {
+        let mut tried_agent = env::var("SSH_AUTH_SOCK").is_err();
+        let mut ssh_keys = self.ssh_keys();
+        ssh_keys.reverse();
+        let mut token = self.token.clone();
+
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(move |url, username_from_url, allowed_types| {
+            let username = username_from_url.unwrap_or("git");
+            if allowed_types.contains(CredentialType::SSH_KEY) {
+                if !tried_agent {
+                    tried_agent = true;
+                    return Cred::ssh_key_from_agent(username);
+                }
+                if let Some(ssh_key) = ssh_keys.pop() {
+                    return Cred::ssh_key(username, None, &ssh_key, None);
+                }
+            }
+            if allowed_types.contains(CredentialType::USER_PASS_PLAINTEXT) && is_token_host(url) {
+                if let Some(token) = token.take() {
+                    return Cred::userpass_plaintext(TOKEN_USERNAME, &token);
+                }
+            }
+
+            Err(git2::Error::from_str("no credentials left to try"))
+        });
+
+        callbacks
+    }
+}
+
+/// Check if the token may be sent to the server at the given url.
+fn is_token_host(url: &str) -> bool {
+    let (host, _) = split_url(url);
+    host.eq_ignore_ascii_case(TOKEN_HOST)
+}
+
+fn get_config_file() -> Option<PathBuf> {
+    BaseDirs::new().map(|base_dirs| {
+        base_dirs
+            .config_dir()
+            .join(APPLICATION_NAME)
+            .join(CONFIG_FILE)
+    })
+}
+
+/// Add a hint on how to configure credentials to errors caused by failing to authenticate.
+pub fn explain_auth_error(url: &str, err: git2::Error) -> Box<dyn Error> {
+    let is_auth_error = err.code() == ErrorCode::Auth
+        || err.class() == ErrorClass::Ssh
+        || err.message().contains("no credentials left to try");
+    if !is_auth_error {
+        return Box::new(err);
+    }
+
+    let hint = if url.starts_with("https://") && is_token_host(url) {
+        format!(
+            "set one of {} or `token` in the evg-bonsai config file",
+            TOKEN_ENV_VARS.join(", ")
+        )
+    } else if url.starts_with("https://") {
+        "configure credentials for the host in git, the github token is only sent to github.com"
+            .to_string()
+    } else {
+        "make sure an ssh-agent is running with your key loaded, or that a key exists in ~/.ssh \
+         or is set as `ssh_key` in the evg-bonsai config file"
+            .to_string()
+    };
+    format!(
+        "Authentication failed for {}: {}. To fix, {}.",
+        url,
+        err.message(),
+        hint
+    )
+    .into()
+}
@@ -1,132 +1,308 @@
 use crate::landscape::pot::GithubVersionSpec;
+use crate::pot::cache::mark_used;
+use crate::pot::credentials::{explain_auth_error, GitConfig};
 use directories_next::BaseDirs;
 use git2::build::{CheckoutBuilder, RepoBuilder};
-use git2::{AutotagOption, Cred, FetchOptions, Oid, RemoteCallbacks, Repository};
+use git2::{AutotagOption, FetchOptions, Oid, Repository};
+use semver::{Version, VersionReq};
 use simple_error::bail;
-use std::env;
 use std::error::Error;
-use std::fs::create_dir_all;
+use std::fs::{create_dir_all, remove_dir_all, rename};
 use std::path::{Path, PathBuf};
 
-const APPLICATION_NAME: &str = "evg-bonsai";
+pub const APPLICATION_NAME: &str = "evg-bonsai";
+/// Directory in the cache holding a clone of each remote repository.
+pub const REPOS_DIR: &str = "repos";
+/// Directory in the cache holding a checkout of each commit that has been used.
+pub const CHECKOUTS_DIR: &str = "checkouts";
+/// Refspecs used to refresh a cached clone.
+const FETCH_REFSPECS: &[&str] = &[
+    "+refs/heads/*:refs/remotes/origin/*",
+    "+refs/tags/*:refs/tags/*",
+];
+/// Reference pointing at the default branch of the remote.
+const ORIGIN_HEAD: &str = "refs/remotes/origin/HEAD";
 
-fn get_cache_dir() -> Result<PathBuf, Box<dyn Error>> {
-    if let Some(base_dirs) = BaseDirs::new() {
-        let mut cache_dir = base_dirs.cache_dir().to_owned();
-        cache_dir.push(APPLICATION_NAME);
-        if !cache_dir.exists() {
-            create_dir_all(&cache_dir)?;
-        }
-
-        Ok(cache_dir)
+/// Get the directory remote repositories are cached in, creating it if needed.
+pub fn get_cache_dir(git_config: &GitConfig) -> Result<PathBuf, Box<dyn Error>> {
+    let cache_dir = if let Some(cache_dir) = &git_config.cache_dir {
+        cache_dir.clone()
+    } else if let Some(base_dirs) = BaseDirs::new() {
+        base_dirs.cache_dir().join(APPLICATION_NAME)
     } else {
         bail!("No cache directory found.")
+    };
+
+    if !cache_dir.exists() {
+        create_dir_all(&cache_dir)?;
     }
+    Ok(cache_dir)
 }
 
-fn create_ssh_fetch_options() -> FetchOptions<'static> {
-    let mut callbacks = RemoteCallbacks::new();
-    callbacks.credentials(|_url, username_from_url, _allowed_types| {
-        Cred::ssh_key(
-            username_from_url.unwrap(),
-            None,
-            Path::new(&format!("{}/.ssh/id_rsa", env::var("HOME").unwrap())),
-            None,
-        )
-    });
-
+fn create_fetch_options(git_config: &GitConfig) -> FetchOptions<'static> {
     let mut fo = FetchOptions::new();
-    fo.remote_callbacks(callbacks);
+    fo.remote_callbacks(git_config.create_callbacks());
     fo.download_tags(AutotagOption::All);
 
     fo
 }
 
-fn clone_via_ssh(url: &str, path: &Path) -> Result<Repository, Box<dyn Error>> {
-    let fo = create_ssh_fetch_options();
-    Ok(RepoBuilder::new().fetch_options(fo).clone(url, path)?)
-}
-
-fn format_spec(branch_name: &str) -> String {
-    format!("refs/heads/{}", branch_name)
+fn clone_repository(
+    url: &str,
+    path: &Path,
+    git_config: &GitConfig,
+) -> Result<Repository, Box<dyn Error>> {
+    let fo = create_fetch_options(git_config);
+    RepoBuilder::new()
+        .bare(true)
+        .fetch_options(fo)
+        .clone(url, path)
+        .map_err(|err| explain_auth_error(url, err))
 }
 
 fn format_rm_spec(remote: &str, branch_name: &str) -> String {
     format!("refs/remotes/{}/{}", remote, branch_name)
 }
 
-fn fast_forward(repo: &Repository) -> Result<(), Box<dyn Error>> {
-    let branch = "master";
-    let mut fo = create_ssh_fetch_options();
-
-    repo.find_remote("origin")?
-        .fetch(&[] as &[&str], Some(&mut fo), None)?;
-    let fetch_head = repo.find_reference("FETCH_HEAD")?;
-    let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
-    let analysis = repo.merge_analysis(&[&fetch_commit])?;
-    if analysis.0.is_up_to_date() {
-        Ok(())
-    } else if analysis.0.is_fast_forward() {
-        let refname = format!("refs/heads/{}", branch);
-        let mut reference = repo.find_reference(&refname)?;
-        reference.set_target(fetch_commit.id(), "Fast-Forward")?;
-        repo.set_head(&refname)?;
-        Ok(repo.checkout_head(Some(CheckoutBuilder::default().force()))?)
-    } else {
-        bail!("Fast-forward only")
+/// Fetch all branches and tags of the remote, updating `origin/HEAD` to its default branch.
+///
+/// Refs are force-updated so branches that were force-pushed or tags that were moved replace
+/// the cached ones instead of failing the fetch.
+fn fetch_remote(
+    repo: &Repository,
+    url: &str,
+    git_config: &GitConfig,
+) -> Result<(), Box<dyn Error>> {
+    let mut fo = create_fetch_options(git_config);
+
+    repo.remote_set_url("origin", url)?;
+    let mut remote = repo.find_remote("origin")?;
+    remote
+        .fetch(FETCH_REFSPECS, Some(&mut fo), None)
+        .map_err(|err| explain_auth_error(url, err))?;
+
+    if let Ok(default_branch) = remote.default_branch() {
+        if let Some(branch) = default_branch
+            .as_str()
+            .and_then(|b| b.strip_prefix("refs/heads/"))
+        {
+            repo.reference_symbolic(
+                ORIGIN_HEAD,
+                &format_rm_spec("origin", branch),
+                true,
+                "Update default branch",
+            )?;
+        }
     }
+
+    Ok(())
 }
 
-fn checkout_revision(repo: &Repository, revision: &str) -> Result<(), Box<dyn Error>> {
-    let revspec = format_spec(revision);
+fn has_commit(repo: &Repository, revision: &str) -> bool {
+    Oid::from_str(revision)
+        .and_then(|oid| repo.find_commit(oid))
+        .is_ok()
+}
 
+fn resolve_revision(repo: &Repository, revision: &str) -> Result<Oid, Box<dyn Error>> {
     let oid = Oid::from_str(revision)?;
-    let commit = repo.find_commit(oid)?;
-    let obj_result = repo.revparse_single(&revspec);
-    if obj_result.is_err() {
-        let _branch = repo.branch(revision, &commit, false)?;
-    }
-    let obj = repo.revparse_single(&revspec)?;
+    Ok(repo.find_commit(oid)?.id())
+}
 
-    repo.checkout_tree(&obj, None)?;
-    repo.set_head_detached(oid)?;
+fn resolve_branch(repo: &Repository, branch_name: &str) -> Result<Oid, Box<dyn Error>> {
+    let revspec = format_rm_spec("origin", branch_name);
+    Ok(repo.revparse_single(&revspec)?.peel_to_commit()?.id())
+}
 
-    Ok(())
+fn resolve_tag(repo: &Repository, tag_name: &str) -> Result<Oid, Box<dyn Error>> {
+    let obj = repo.revparse_single(&format!("refs/tags/{}", tag_name))?;
+    Ok(obj.peel_to_commit()?.id())
 }
 
-fn checkout_branch(repo: &Repository, branch_name: &str) -> Result<(), Box<dyn Error>> {
-    let revspec = format_rm_spec("origin", branch_name);
-    let obj = repo.revparse_single(&revspec)?;
+/// Find the tag with the highest semantic version matching the given requirement.
+///
+/// Tags that are not semantic versions, optionally prefixed with a 'v', are ignored.
+fn find_version_tag(repo: &Repository, requirement: &str) -> Result<String, Box<dyn Error>> {
+    let version_req = VersionReq::parse(requirement)
+        .map_err(|err| format!("Invalid version requirement '{}': {}", requirement, err))?;
 
-    repo.checkout_tree(&obj, None)?;
-    repo.set_head(&revspec)?;
+    let tag_names = repo.tag_names(None)?;
+    let best_match = tag_names
+        .iter()
+        .flatten()
+        .filter_map(|tag| {
+            Version::parse(tag.trim_start_matches('v'))
+                .ok()
+                .map(|version| (version, tag))
+        })
+        .filter(|(version, _)| version_req.matches(version))
+        .max_by(|(a, _), (b, _)| a.cmp(b));
 
+    match best_match {
+        Some((_, tag)) => Ok(tag.to_string()),
+        None => bail!("No tag matches version requirement '{}'", requirement),
+    }
+}
+
+/// Resolve the default branch of the remote, falling back to the `HEAD` of the clone.
+fn resolve_default_branch(repo: &Repository) -> Result<Oid, Box<dyn Error>> {
+    let head = match repo.find_reference(ORIGIN_HEAD) {
+        Ok(origin_head) => origin_head.resolve()?,
+        Err(_) => repo.head()?,
+    };
+    Ok(head.peel_to_commit()?.id())
+}
+
+/// Write the tree of the given commit to its own directory, if it has not been already.
+///
+/// The tree is written to a temporary directory first so an interrupted checkout is never
+/// mistaken for a complete one.
+fn materialize_commit(
+    repo: &Repository,
+    commit: Oid,
+    checkout_dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    if checkout_dir.exists() {
+        return Ok(());
+    }
+
+    let partial_dir = checkout_dir.with_extension("partial");
+    if partial_dir.exists() {
+        remove_dir_all(&partial_dir)?;
+    }
+    create_dir_all(&partial_dir)?;
+
+    let tree = repo.find_commit(commit)?.into_object();
+    let mut checkout = CheckoutBuilder::new();
+    checkout
+        .target_dir(&partial_dir)
+        .force()
+        .recreate_missing(true);
+    repo.checkout_tree(&tree, Some(&mut checkout))?;
+
+    rename(&partial_dir, checkout_dir)?;
     Ok(())
 }
 
+/// Get a local checkout of the repository at the given url.
+///
+/// Clones are cached under `repos/<cache_path>` and every commit is checked out to its own
+/// directory under `checkouts/<cache_path>/<commit>`, so several revisions of the same
+/// repository can be used at once. If `locked_commit` is given, that exact commit is used
+/// instead of resolving `maybe_version`. Returns the path to the checkout and its commit.
+///
+/// In offline mode the network is never used, everything is resolved from the existing clone.
 pub fn get_repository(
-    owner: &str,
-    repo: &str,
+    cache_path: &Path,
+    url: &str,
+    git_config: &GitConfig,
     maybe_version: &Option<GithubVersionSpec>,
-) -> Result<PathBuf, Box<dyn Error>> {
-    let base_dir = get_cache_dir()?;
-    let mut repo_dir = base_dir;
-    repo_dir.push(repo);
+    locked_commit: Option<&str>,
+) -> Result<(PathBuf, String), Box<dyn Error>> {
+    let cache_dir = get_cache_dir(git_config)?;
+    let repo_dir = cache_dir.join(REPOS_DIR).join(cache_path);
     let repo = if repo_dir.exists() {
-        Repository::open(&repo_dir)?
+        let repo = Repository::open(&repo_dir)?;
+        let pinned_commit = match (locked_commit, maybe_version) {
+            (Some(commit), _) => Some(commit),
+            (None, Some(GithubVersionSpec::Revision(rev))) => Some(rev.as_str()),
+            _ => None,
+        };
+        match pinned_commit {
+            Some(commit) if has_commit(&repo, commit) => (),
+            Some(commit) if git_config.offline => {
+                bail!(
+                    "Commit {} of {} is not in the cache, fetch it with `evg-bonsai cache fetch`",
+                    commit,
+                    url
+                )
+            }
+            _ if git_config.offline => (),
+            _ => fetch_remote(&repo, url, git_config)?,
+        }
+        repo
+    } else if git_config.offline {
+        bail!(
+            "{} is not in the cache, fetch it with `evg-bonsai cache fetch`",
+            url
+        )
     } else {
-        let url = format!("git@github.com:{}/{}.git", owner, repo);
-        clone_via_ssh(&url, &repo_dir.as_path())?
+        clone_repository(url, repo_dir.as_path(), git_config)?
     };
 
-    fast_forward(&repo)?;
+    let commit = if let Some(commit) = locked_commit {
+        resolve_revision(&repo, commit)?
+    } else {
+        match maybe_version {
+            Some(GithubVersionSpec::Branch(branch)) => resolve_branch(&repo, branch)?,
+            Some(GithubVersionSpec::Revision(rev)) => resolve_revision(&repo, rev)?,
+            Some(GithubVersionSpec::Tag(tag)) => resolve_tag(&repo, tag)?,
+            Some(GithubVersionSpec::Version(requirement)) => {
+                resolve_tag(&repo, &find_version_tag(&repo, requirement)?)?
+            }
+            None => resolve_default_branch(&repo)?,
+        }
+    };
+
+    let checkout_dir = cache_dir
+        .join(CHECKOUTS_DIR)
+        .join(cache_path)
+        .join(commit.to_string());
+    materialize_commit(&repo, commit, &checkout_dir)?;
+    mark_used(&repo_dir)?;
+
+    Ok((checkout_dir, commit.to_string()))
+}
 
-    if let Some(version) = maybe_version {
-        match version {
-            GithubVersionSpec::Branch(branch) => checkout_branch(&repo, branch)?,
-            GithubVersionSpec::Revision(rev) => checkout_revision(&repo, rev)?,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+    use std::env::temp_dir;
+
+    /// Create a repository with a single commit tagged with each of the given names.
+    fn create_tagged_repo(name: &str, tags: &[&str]) -> (PathBuf, Repository) {
+        let path = temp_dir().join(format!("evg-bonsai-{}-{}", name, std::process::id()));
+        if path.exists() {
+            remove_dir_all(&path).unwrap();
+        }
+        let repo = Repository::init(&path).unwrap();
+        {
+            let signature = Signature::now("test", "test@example.com").unwrap();
+            let tree_id = repo.index().unwrap().write_tree().unwrap();
+            let tree = repo.find_tree(tree_id).unwrap();
+            let commit = repo
+                .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+                .unwrap();
+            let object = repo.find_object(commit, None).unwrap();
+            for tag in tags {
+                repo.tag_lightweight(tag, &object, false).unwrap();
+            }
         }
+        (path, repo)
     }
 
-    Ok(repo_dir.as_path().to_owned())
+    #[test]
+    fn test_find_version_tag() {
+        let (path, repo) = create_tagged_repo(
+            "find-version-tag",
+            &[
+                "v1.0.0",
+                "v1.2.0",
+                "1.3.0-beta",
+                "v2.0.0",
+                "latest",
+                "v1.10.1",
+            ],
+        );
+
+        assert_eq!(find_version_tag(&repo, "^1").unwrap(), "v1.10.1");
+        assert_eq!(find_version_tag(&repo, "~1.2").unwrap(), "v1.2.0");
+        assert_eq!(find_version_tag(&repo, ">=1.0.0").unwrap(), "v2.0.0");
+        assert_eq!(find_version_tag(&repo, "=1.0.0").unwrap(), "v1.0.0");
+        assert!(find_version_tag(&repo, "^3").is_err());
+        assert!(find_version_tag(&repo, "not a version").is_err());
+
+        remove_dir_all(path).unwrap();
+    }
 }
@@ -0,0 +1,147 @@
+use crate::landscape::pot::{GithubVersionSpec, RemoteId, RemoteSourceDesc};
+use crate::pot::credentials::GitConfig;
+use crate::pot::github_service::get_repository;
+use crate::pot::lockfile::{BonsaiLock, LockedSource};
+use crate::pot::vendor::BonsaiVendor;
+use simple_error::bail;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Fetches remote pot sources, honouring and recording the commits they are locked to.
+#[derive(Default)]
+pub struct PotLoader {
+    /// Commits pinned by an existing lock file.
+    locked: BonsaiLock,
+    /// Commits each source has been resolved to while loading.
+    pub resolved: BonsaiLock,
+    /// Repositories (`owner/repo`) whose locked commits should be ignored, all if empty.
+    updating: Option<Vec<String>>,
+    /// Settings used to access remote repositories.
+    git_config: GitConfig,
+    /// Checkouts of the sources that have already been resolved, so each is only fetched once.
+    checkouts: BTreeMap<(RemoteId, Option<GithubVersionSpec>), PathBuf>,
+    /// Vendored copies to load sources from instead of fetching them.
+    vendor: Option<BonsaiVendor>,
+}
+
+impl PotLoader {
+    pub fn new(locked: BonsaiLock, git_config: GitConfig) -> Self {
+        Self {
+            locked,
+            git_config,
+            ..Default::default()
+        }
+    }
+
+    /// Re-resolve the given repositories (`owner/repo`) instead of using their locked commits.
+    /// If no repositories are given, all sources are re-resolved.
+    pub fn with_updates(mut self, repos: Vec<String>) -> Self {
+        self.updating = Some(repos);
+        self
+    }
+
+    /// Load every remote source from the given vendored copies, never fetching anything.
+    pub fn with_vendor(mut self, vendor: BonsaiVendor) -> Self {
+        self.vendor = Some(vendor);
+        self
+    }
+
+    fn is_updating(&self, remote: &RemoteId, version: &Option<GithubVersionSpec>) -> bool {
+        if let Some(GithubVersionSpec::Revision(_)) = version {
+            return false;
+        }
+
+        match &self.updating {
+            Some(repos) if repos.is_empty() => true,
+            Some(repos) => repos.contains(&remote.to_string()),
+            None => false,
+        }
+    }
+
+    fn get_url(&self, source: &dyn RemoteSourceDesc) -> String {
+        let transport = source.get_transport().unwrap_or(self.git_config.transport);
+        source.get_remote_id().get_url(transport)
+    }
+
+    /// Get a local checkout of the given source.
+    ///
+    /// A source that has already been resolved is reused as is. A source that is pinned in the
+    /// lock file and not being updated is checked out at that exact commit. If vendored copies
+    /// are used, the source is loaded from them instead.
+    pub fn get_repository(
+        &mut self,
+        source: &dyn RemoteSourceDesc,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let remote = source.get_remote_id();
+        let version = source.get_version();
+        let checkout_key = (remote.clone(), version.clone());
+        if let Some(repo_path) = self.checkouts.get(&checkout_key) {
+            return Ok(repo_path.clone());
+        }
+
+        if let Some(vendor) = &self.vendor {
+            let vendored = match vendor.find(&remote, version) {
+                Some(vendored) => vendored,
+                None => bail!(
+                    "{} is not vendored in {}, run `evg-bonsai vendor` to add it",
+                    remote,
+                    vendor.dir.display()
+                ),
+            };
+            if let Some(locked) = self.locked.find(&remote, version) {
+                if locked.commit != vendored.source.commit {
+                    bail!(
+                        "{} is locked to {} but vendored at {}, run `evg-bonsai vendor` to update it",
+                        remote,
+                        locked.commit,
+                        vendored.source.commit
+                    );
+                }
+            }
+            let repo_path = vendor.dir.join(&vendored.path);
+            self.resolved.insert(vendored.source.clone());
+            self.checkouts.insert(checkout_key, repo_path.clone());
+            return Ok(repo_path);
+        }
+
+        let pinned_commit = if self.is_updating(&remote, version) {
+            None
+        } else {
+            self.locked.find(&remote, version).map(|s| s.commit.clone())
+        };
+
+        let (repo_path, commit) = get_repository(
+            &remote.get_cache_path(),
+            &self.get_url(source),
+            &self.git_config,
+            version,
+            pinned_commit.as_deref(),
+        )?;
+        self.resolved.insert(LockedSource {
+            remote,
+            version: version.clone(),
+            commit,
+        });
+        self.checkouts.insert(checkout_key, repo_path.clone());
+
+        Ok(repo_path)
+    }
+
+    /// Get a local checkout of the given source at an exact commit, without recording it.
+    pub fn get_repository_at(
+        &self,
+        source: &dyn RemoteSourceDesc,
+        commit: &str,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let (repo_path, _) = get_repository(
+            &source.get_remote_id().get_cache_path(),
+            &self.get_url(source),
+            &self.git_config,
+            source.get_version(),
+            Some(commit),
+        )?;
+
+        Ok(repo_path)
+    }
+}
@@ -0,0 +1,77 @@
+use crate::landscape::pot::{GithubVersionSpec, RemoteId, RemoteSourceDesc};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{read_to_string, write};
+use std::path::Path;
+
+pub const LOCK_FILE: &str = "bonsai.lock";
+
+/// The exact commit a pot source was resolved to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LockedSource {
+    #[serde(flatten)]
+    pub remote: RemoteId,
+    /// Version of the source that was requested.
+    #[serde(flatten)]
+    pub version: Option<GithubVersionSpec>,
+    /// Commit the requested version was resolved to.
+    pub commit: String,
+}
+
+impl LockedSource {
+    fn matches(&self, remote: &RemoteId, version: &Option<GithubVersionSpec>) -> bool {
+        &self.remote == remote && &self.version == version
+    }
+}
+
+impl RemoteSourceDesc for LockedSource {
+    fn get_remote_id(&self) -> RemoteId {
+        self.remote.clone()
+    }
+
+    fn get_version(&self) -> &Option<GithubVersionSpec> {
+        &self.version
+    }
+}
+
+/// Lock file pinning every remote pot source used by a landscape to an exact commit.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BonsaiLock {
+    pub sources: Vec<LockedSource>,
+}
+
+impl BonsaiLock {
+    /// Read a lock file, an empty lock is used if the file does not exist.
+    pub fn from_path(path: &Path) -> Result<BonsaiLock, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(BonsaiLock::default());
+        }
+
+        let contents = read_to_string(path)?;
+        Ok(serde_yaml::from_str(&contents)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut sources = self.sources.clone();
+        sources.sort_by(|a, b| (&a.remote, &a.version).cmp(&(&b.remote, &b.version)));
+        let contents = serde_yaml::to_string(&BonsaiLock { sources })?;
+        write(path, contents)?;
+
+        Ok(())
+    }
+
+    pub fn find(
+        &self,
+        remote: &RemoteId,
+        version: &Option<GithubVersionSpec>,
+    ) -> Option<&LockedSource> {
+        self.sources.iter().find(|s| s.matches(remote, version))
+    }
+
+    /// Add the given source, replacing any existing entry for the same source.
+    pub fn insert(&mut self, locked_source: LockedSource) {
+        self.sources
+            .retain(|s| !s.matches(&locked_source.remote, &locked_source.version));
+        self.sources.push(locked_source);
+    }
+}
@@ -1,42 +1,168 @@
+use glob::{glob, MatchOptions, Pattern};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use simple_error::bail;
+use std::collections::{BTreeSet, HashMap};
 use std::error::Error;
 use std::fs;
 use std::fs::create_dir_all;
-use std::path::Path;
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BonsaiPotMetadata {
     pub name: String,
     pub path: String,
     pub description: String,
+    /// Files, directories or glob patterns of support files to copy with the pot.
     pub include_files: Option<Vec<String>>,
+    /// Glob patterns of files to leave out of `include_files`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_files: Option<Vec<String>>,
 }
 
 impl BonsaiPotMetadata {
+    /// Copy the support files of this pot, returning the relative paths of the files copied.
     pub fn copy_files_to_destination(
         &self,
         base_dir: &Path,
         destination_dir: &Path,
-    ) -> Result<(), Box<dyn Error>> {
-        if let Some(files) = &self.include_files {
-            for file in files {
-                let mut source_file = base_dir.to_path_buf();
-                source_file.push(file);
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        match &self.include_files {
+            Some(files) => {
+                let excludes = self.exclude_files.as_deref().unwrap_or_default();
+                copy_include_files(files, excludes, base_dir, destination_dir)
+            }
+            None => Ok(vec![]),
+        }
+    }
+}
 
-                let mut destination_file = destination_dir.to_path_buf();
-                destination_file.push(file);
+fn is_glob(entry: &str) -> bool {
+    entry.contains(['*', '?', '['])
+}
 
-                if let Some(dest_dir) = destination_file.parent() {
-                    create_dir_all(dest_dir)?;
-                }
+/// Add every file under the given directory to `files`.
+fn add_dir_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            add_dir_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
 
-                fs::copy(source_file, destination_file)?;
-            }
+    Ok(())
+}
+
+/// Find the files an `include_files` entry refers to, it may be a file, directory or glob.
+fn find_include_files(entry: &str, base_dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let is_relative = Path::new(entry)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !is_relative {
+        bail!(
+            "Support file '{}' must be a relative path inside the pot directory",
+            entry
+        );
+    }
+
+    let matches: Vec<PathBuf> = if is_glob(entry) {
+        let pattern = format!("{}/{}", Pattern::escape(&base_dir.to_string_lossy()), entry);
+        glob(&pattern)?.collect::<Result<_, _>>()?
+    } else {
+        vec![base_dir.join(entry)]
+    };
+
+    let mut files = vec![];
+    for path in matches {
+        if path.is_dir() {
+            add_dir_files(&path, &mut files)?;
+        } else if path.exists() {
+            files.push(path);
         }
+    }
+    if files.is_empty() {
+        bail!("Support file '{}' did not match any files", entry);
+    }
+
+    Ok(files)
+}
+
+/// Check if a path, or any directory containing it, matches one of the given patterns.
+fn matches_any(relative_path: &Path, patterns: &[Pattern]) -> bool {
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    relative_path.ancestors().any(|path| {
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches_path_with(path, options))
+    })
+}
+
+/// Compile `include_files` or `exclude_files` entries to patterns, entries without glob
+/// characters only match themselves.
+fn compile_patterns(entries: &[String]) -> Result<Vec<Pattern>, Box<dyn Error>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let entry = entry.trim_start_matches("./").trim_end_matches('/');
+            if is_glob(entry) {
+                Pattern::new(entry)
+            } else {
+                Pattern::new(&Pattern::escape(entry))
+            }
+        })
+        .collect::<Result<_, _>>()
+        .map_err(|err| err.into())
+}
+
+/// Check if a relative path is selected by the given `include_files` and not excluded by the
+/// given `exclude_files`.
+pub fn is_included(
+    relative_path: &Path,
+    includes: &[String],
+    excludes: &[String],
+) -> Result<bool, Box<dyn Error>> {
+    Ok(matches_any(relative_path, &compile_patterns(includes)?)
+        && !matches_any(relative_path, &compile_patterns(excludes)?))
+}
+
+/// Copy the given files, relative to `base_dir`, to the same relative paths under
+/// `destination_dir`.
+///
+/// Entries may be files, directories (copied recursively) or glob patterns. Files matching one
+/// of the `excludes` patterns are skipped. File permissions, such as executable bits, are kept.
+/// Returns the relative paths of all files copied.
+pub fn copy_include_files(
+    files: &[String],
+    excludes: &[String],
+    base_dir: &Path,
+    destination_dir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let excludes = compile_patterns(excludes)?;
 
-        Ok(())
+    let mut copied = BTreeSet::new();
+    for entry in files {
+        for source_file in find_include_files(entry, base_dir)? {
+            let relative_path = source_file.strip_prefix(base_dir)?.to_path_buf();
+            if matches_any(&relative_path, &excludes) || copied.contains(&relative_path) {
+                continue;
+            }
+
+            let destination_file = destination_dir.join(&relative_path);
+            if let Some(dest_dir) = destination_file.parent() {
+                create_dir_all(dest_dir)?;
+            }
+
+            // `fs::copy` also copies the permission bits of the file.
+            fs::copy(&source_file, destination_file)?;
+            copied.insert(relative_path);
+        }
     }
+
+    Ok(copied.into_iter().collect())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -50,17 +176,87 @@ impl BonsaiPotManifest {
         Ok(serde_yaml::from_str(&contents)?)
     }
 
+    /// Copy the support files of the used pots, each into its support file directory under
+    /// `destination_dir`. Returns the paths of the files copied, relative to `destination_dir`.
     pub fn copy_support_files(
         &self,
         base_dir: &Path,
         destination_dir: &Path,
-        used_pots: &HashSet<String>,
-    ) -> Result<(), Box<dyn Error>> {
-        self.bonsai_pots
-            .iter()
-            .filter(|p| used_pots.contains(&p.name))
-            .try_for_each(|p| p.copy_files_to_destination(base_dir, destination_dir))?;
+        used_pots: &HashMap<String, PathBuf>,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut copied = vec![];
+        for pot_md in &self.bonsai_pots {
+            if let Some(support_files_dir) = used_pots.get(&pot_md.name) {
+                let files = pot_md.copy_files_to_destination(
+                    base_dir,
+                    &destination_dir.join(support_files_dir),
+                )?;
+                copied.extend(files.into_iter().map(|f| support_files_dir.join(f)));
+            }
+        }
+
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    fn included(path: &str, includes: &[&str], excludes: &[&str]) -> bool {
+        is_included(Path::new(path), &entries(includes), &entries(excludes)).unwrap()
+    }
+
+    #[test]
+    fn test_compile_patterns() {
+        let patterns = compile_patterns(&entries(&[
+            "./scripts/run.sh",
+            "scripts/",
+            "bin/run[12].sh",
+        ]))
+        .unwrap();
+
+        assert_eq!(patterns.len(), 3);
+        assert!(patterns[0].matches("scripts/run.sh"));
+        assert!(patterns[1].matches("scripts"));
+        assert!(patterns[2].matches("bin/run1.sh"));
+        assert!(!patterns[2].matches("bin/run3.sh"));
+    }
+
+    #[test]
+    fn test_compile_patterns_rejects_invalid_globs() {
+        assert!(compile_patterns(&entries(&["scripts/***.sh"])).is_err());
+    }
+
+    #[test]
+    fn test_is_included_files_and_directories() {
+        assert!(included("scripts/run.sh", &["scripts/run.sh"], &[]));
+        assert!(included("scripts/lib/util.sh", &["scripts"], &[]));
+        assert!(included("scripts/lib/util.sh", &["./scripts/"], &[]));
+        assert!(!included("scripts_old/run.sh", &["scripts"], &[]));
+        assert!(!included("other/run.sh", &["scripts/run.sh"], &[]));
+    }
+
+    #[test]
+    fn test_is_included_globs() {
+        assert!(included("bin/start.sh", &["bin/*.sh"], &[]));
+        assert!(!included("bin/sub/start.sh", &["bin/*.sh"], &[]));
+        assert!(included("bin/sub/start.sh", &["bin/**/*.sh"], &[]));
+        assert!(!included("bin/start.py", &["bin/*.sh"], &[]));
+    }
 
-        Ok(())
+    #[test]
+    fn test_is_included_excludes() {
+        assert!(!included(
+            "scripts/test/run.sh",
+            &["scripts"],
+            &["scripts/test"]
+        ));
+        assert!(!included("scripts/run.pyc", &["scripts"], &["**/*.pyc"]));
+        assert!(included("scripts/run.py", &["scripts"], &["**/*.pyc"]));
     }
 }
@@ -1,3 +1,9 @@
+pub mod cache;
+pub mod credentials;
 pub mod github_service;
+pub mod loader;
+pub mod lockfile;
 pub mod manifest;
 pub mod remote_pot;
+pub mod update;
+pub mod vendor;
@@ -1,14 +1,14 @@
-use crate::landscape::pot::{BonsaiPot, GithubSourceDesc};
-use crate::pot::github_service::get_repository;
+use crate::landscape::pot::{BonsaiPot, RemoteSourceDesc};
+use crate::pot::loader::PotLoader;
 use crate::pot::manifest::BonsaiPotManifest;
 use simple_error::bail;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-const MANIFEST_FILE: &str = "bonsai.manifest.yml";
+pub const MANIFEST_FILE: &str = "bonsai.manifest.yml";
 
-fn find_manifest(repo_path: &Path) -> Result<BonsaiPotManifest, Box<dyn Error>> {
+pub fn find_manifest(repo_path: &Path) -> Result<BonsaiPotManifest, Box<dyn Error>> {
     let mut manifest_path = repo_path.to_path_buf();
     manifest_path.push(MANIFEST_FILE);
 
@@ -19,36 +19,49 @@ fn find_manifest(repo_path: &Path) -> Result<BonsaiPotManifest, Box<dyn Error>>
     BonsaiPotManifest::from_path(&manifest_path)
 }
 
-pub fn get_remote_pots(github_source: &GithubSourceDesc) -> Result<Vec<BonsaiPot>, Box<dyn Error>> {
-    let repo_path = get_repository(
-        &github_source.owner,
-        &github_source.repo,
-        &github_source.version,
-    )?;
-    let manifest = find_manifest(repo_path.as_path())?;
+pub fn get_remote_pots(
+    source: &dyn RemoteSourceDesc,
+    pot_loader: &mut PotLoader,
+) -> Result<Vec<BonsaiPot>, Box<dyn Error>> {
+    let repo_path = pot_loader.get_repository(source)?;
+    read_manifest_pots(&repo_path)
+}
+
+/// Read all pots listed in the manifest of the given directory, usually a repository checkout.
+pub fn read_manifest_pots(repo_path: &Path) -> Result<Vec<BonsaiPot>, Box<dyn Error>> {
+    let manifest = find_manifest(repo_path)?;
     manifest
         .bonsai_pots
         .iter()
         .map(|pot_md| {
-            let mut pot_path = repo_path.clone();
+            let mut pot_path = repo_path.to_path_buf();
             pot_path.push(&pot_md.path);
-            BonsaiPot::from_path(pot_path.as_path())
+            let mut pot = BonsaiPot::from_path(pot_path.as_path())?;
+            pot.include_files = pot_md.include_files.clone();
+            pot.exclude_files = pot_md.exclude_files.clone();
+            Ok(pot)
         })
         .collect()
 }
 
 pub fn copy_support_files(
-    github_source: &GithubSourceDesc,
+    source: &dyn RemoteSourceDesc,
+    destination_dir: &Path,
+    used_pots: &HashMap<String, PathBuf>,
+    pot_loader: &mut PotLoader,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let repo_path = pot_loader.get_repository(source)?;
+    copy_manifest_support_files(repo_path.as_path(), destination_dir, used_pots)
+}
+
+/// Copy the support files of the used pots listed in the manifest of the given directory.
+///
+/// Returns the paths of the files copied, relative to `destination_dir`.
+pub fn copy_manifest_support_files(
+    pots_dir: &Path,
     destination_dir: &Path,
-    used_pots: &HashSet<String>,
-) -> Result<(), Box<dyn Error>> {
-    let repo_path = get_repository(
-        &github_source.owner,
-        &github_source.repo,
-        &github_source.version,
-    )?;
-    let manifest = find_manifest(repo_path.as_path())?;
-    manifest.copy_support_files(repo_path.as_path(), destination_dir, used_pots)?;
-
-    Ok(())
+    used_pots: &HashMap<String, PathBuf>,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let manifest = find_manifest(pots_dir)?;
+    manifest.copy_support_files(pots_dir, destination_dir, used_pots)
 }
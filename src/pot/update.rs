@@ -0,0 +1,130 @@
+use crate::landscape::pot::{BonsaiPot, GithubVersionSpec};
+use crate::pot::loader::PotLoader;
+use crate::pot::lockfile::{BonsaiLock, LockedSource};
+use crate::pot::remote_pot::read_manifest_pots;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+
+/// How a pot function differs between two revisions of a source.
+pub enum FunctionChange {
+    Added(String),
+    Removed(String),
+    Changed(String),
+}
+
+impl fmt::Display for FunctionChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FunctionChange::Added(name) => write!(f, "+ {}", name),
+            FunctionChange::Removed(name) => write!(f, "- {}", name),
+            FunctionChange::Changed(name) => write!(f, "~ {}", name),
+        }
+    }
+}
+
+/// A locked source that moved to a different commit.
+pub struct SourceUpdate {
+    /// Commit the source was locked to before, `None` if it was not locked.
+    pub previous_commit: Option<String>,
+    pub current: LockedSource,
+    pub changes: Vec<FunctionChange>,
+}
+
+/// Abbreviate a commit hash for display.
+pub fn short_commit(commit: &str) -> &str {
+    &commit[..std::cmp::min(commit.len(), 7)]
+}
+
+impl fmt::Display for SourceUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.current.remote)?;
+        match &self.current.version {
+            Some(GithubVersionSpec::Branch(branch)) => write!(f, " (branch {})", branch)?,
+            Some(GithubVersionSpec::Revision(revision)) => write!(f, " (revision {})", revision)?,
+            Some(GithubVersionSpec::Tag(tag)) => write!(f, " (tag {})", tag)?,
+            Some(GithubVersionSpec::Version(requirement)) => {
+                write!(f, " (version {})", requirement)?
+            }
+            None => (),
+        }
+        match &self.previous_commit {
+            Some(previous) => write!(
+                f,
+                ": {} -> {}",
+                short_commit(previous),
+                short_commit(&self.current.commit)
+            )?,
+            None => write!(f, ": new at {}", short_commit(&self.current.commit))?,
+        }
+        for change in &self.changes {
+            write!(f, "\n    {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Get each function of the pots from a source at the given commit, keyed by `pot:function`.
+fn get_functions_at(
+    source: &LockedSource,
+    commit: &str,
+    pot_loader: &PotLoader,
+) -> Result<BTreeMap<String, serde_yaml::Value>, Box<dyn Error>> {
+    let repo_path = pot_loader.get_repository_at(source, commit)?;
+    let pots: Vec<BonsaiPot> = read_manifest_pots(&repo_path)?;
+
+    let mut functions = BTreeMap::new();
+    for pot in pots {
+        for (fn_name, function) in &pot.functions {
+            functions.insert(pot.get_call_name(fn_name), serde_yaml::to_value(function)?);
+        }
+    }
+    Ok(functions)
+}
+
+fn compare_functions(
+    previous: &BTreeMap<String, serde_yaml::Value>,
+    current: &BTreeMap<String, serde_yaml::Value>,
+) -> Vec<FunctionChange> {
+    let names: BTreeSet<&String> = previous.keys().chain(current.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| match (previous.get(name), current.get(name)) {
+            (None, Some(_)) => Some(FunctionChange::Added(name.clone())),
+            (Some(_), None) => Some(FunctionChange::Removed(name.clone())),
+            (Some(p), Some(c)) if p != c => Some(FunctionChange::Changed(name.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Find the sources that resolve to a different commit than they were previously locked to.
+pub fn find_updates(
+    previous: &BonsaiLock,
+    pot_loader: &PotLoader,
+) -> Result<Vec<SourceUpdate>, Box<dyn Error>> {
+    let mut updates = vec![];
+    for source in &pot_loader.resolved.sources {
+        let previous_commit = previous
+            .find(&source.remote, &source.version)
+            .map(|s| s.commit.clone());
+        if previous_commit.as_ref() == Some(&source.commit) {
+            continue;
+        }
+
+        let changes = match &previous_commit {
+            Some(previous_commit) => compare_functions(
+                &get_functions_at(source, previous_commit, pot_loader)?,
+                &get_functions_at(source, &source.commit, pot_loader)?,
+            ),
+            None => vec![],
+        };
+        updates.push(SourceUpdate {
+            previous_commit,
+            current: source.clone(),
+            changes,
+        });
+    }
+
+    Ok(updates)
+}
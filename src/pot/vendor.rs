@@ -0,0 +1,170 @@
+use crate::is_plain_relative_path;
+use crate::landscape::pot::{GithubVersionSpec, RemoteId};
+use crate::pot::lockfile::LockedSource;
+use crate::pot::remote_pot::{find_manifest, MANIFEST_FILE};
+use crate::pot::update::short_commit;
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::error::Error;
+use std::fs::{copy, create_dir_all, read_to_string, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+
+pub const VENDOR_FILE: &str = "bonsai.vendor.yml";
+
+/// A remote pot source that has been copied into a vendor directory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VendoredSource {
+    #[serde(flatten)]
+    pub source: LockedSource,
+    /// Directory holding the copy, relative to the vendor directory.
+    pub path: PathBuf,
+}
+
+/// Description of the remote pot sources copied into a vendor directory.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BonsaiVendor {
+    pub sources: Vec<VendoredSource>,
+    /// Directory the sources were vendored to.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl BonsaiVendor {
+    /// Read the vendoring config of the given vendor directory.
+    ///
+    /// The path of every source must be inside the vendor directory, since the copies there are
+    /// removed when the directory is vendored again.
+    pub fn from_dir(dir: &Path) -> Result<BonsaiVendor, Box<dyn Error>> {
+        let vendor_file = dir.join(VENDOR_FILE);
+        if !vendor_file.exists() {
+            bail!(
+                "No vendored pots found in {}, create them with `evg-bonsai vendor`",
+                dir.display()
+            );
+        }
+
+        let contents = read_to_string(&vendor_file)?;
+        let mut vendor: BonsaiVendor = serde_yaml::from_str(&contents)?;
+        for source in &vendor.sources {
+            if !is_plain_relative_path(&source.path) {
+                bail!(
+                    "Invalid path '{}' in {}, only paths inside the vendor directory are allowed",
+                    source.path.display(),
+                    vendor_file.display()
+                );
+            }
+        }
+        vendor.dir = dir.to_path_buf();
+        Ok(vendor)
+    }
+
+    pub fn write(&self) -> Result<(), Box<dyn Error>> {
+        let mut sources = self.sources.clone();
+        sources.sort_by(|a, b| {
+            (&a.source.remote, &a.source.version).cmp(&(&b.source.remote, &b.source.version))
+        });
+        let contents = serde_yaml::to_string(&BonsaiVendor {
+            sources,
+            dir: self.dir.clone(),
+        })?;
+        write(self.dir.join(VENDOR_FILE), contents)?;
+
+        Ok(())
+    }
+
+    pub fn find(
+        &self,
+        remote: &RemoteId,
+        version: &Option<GithubVersionSpec>,
+    ) -> Option<&VendoredSource> {
+        self.sources
+            .iter()
+            .find(|s| &s.source.remote == remote && &s.source.version == version)
+    }
+
+    /// Remove the copies of all sources that were previously vendored.
+    pub fn clear(&mut self) -> Result<(), Box<dyn Error>> {
+        for source in &self.sources {
+            let source_dir = self.dir.join(&source.path);
+            if source_dir.exists() {
+                remove_dir_all(source_dir)?;
+            }
+        }
+        self.sources.clear();
+
+        Ok(())
+    }
+
+    /// Copy the manifest, pots and support files of a checked out source into this directory.
+    pub fn add(&mut self, source: &LockedSource, repo_path: &Path) -> Result<(), Box<dyn Error>> {
+        let path = source
+            .remote
+            .get_cache_path()
+            .join(short_commit(&source.commit));
+        let destination_dir = self.dir.join(&path);
+        create_dir_all(&destination_dir)?;
+
+        let manifest = find_manifest(repo_path)?;
+        for pot_md in &manifest.bonsai_pots {
+            let pot_file = destination_dir.join(&pot_md.path);
+            if let Some(parent) = pot_file.parent() {
+                create_dir_all(parent)?;
+            }
+            copy(repo_path.join(&pot_md.path), pot_file)?;
+            pot_md.copy_files_to_destination(repo_path, &destination_dir)?;
+        }
+        copy(
+            repo_path.join(MANIFEST_FILE),
+            destination_dir.join(MANIFEST_FILE),
+        )?;
+
+        self.sources.push(VendoredSource {
+            source: source.clone(),
+            path,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    fn write_vendor_file(name: &str, path: &str) -> PathBuf {
+        let dir = temp_dir().join(format!("evg-bonsai-{}-{}", name, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let contents = format!(
+            "sources:\n  - url: \"file:///tmp/pots\"\n    commit: abc\n    path: {}\n",
+            path
+        );
+        write(dir.join(VENDOR_FILE), contents).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_from_dir_accepts_paths_inside_the_vendor_dir() {
+        let dir = write_vendor_file("vendor-inside", "local/tmp/pots/abc");
+
+        let vendor = BonsaiVendor::from_dir(&dir).unwrap();
+        assert_eq!(vendor.sources[0].path, PathBuf::from("local/tmp/pots/abc"));
+
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_from_dir_rejects_paths_outside_the_vendor_dir() {
+        for (name, path) in [
+            ("vendor-parent", "../../../victim"),
+            ("vendor-nested-parent", "local/../../victim"),
+            ("vendor-absolute", "/tmp/victim"),
+            ("vendor-empty", "\"\""),
+        ] {
+            let dir = write_vendor_file(name, path);
+
+            assert!(BonsaiVendor::from_dir(&dir).is_err(), "{}", path);
+
+            remove_dir_all(dir).unwrap();
+        }
+    }
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::{commit_files, TestDir};
+use evg_bonsai::build_landscape;
+use evg_bonsai::pot::credentials::GitConfig;
+use git2::Repository;
+use std::fs::{read_to_string, remove_dir_all, write};
+use std::path::Path;
+
+const MANIFEST: &str = r#"
+bonsai_pots:
+  - name: greet
+    path: bonsai/greet.bonsai.yml
+    description: Say hello.
+    include_files:
+      - bonsai/hello.sh
+"#;
+
+const POT: &str = r#"
+name: greet
+functions:
+  hello:
+    description: Say hello.
+    actions:
+      - command: shell.exec
+        params:
+          script: bash {{ bonsai_file("bonsai/hello.sh") }}
+"#;
+
+const LANDSCAPE: &str = r#"
+bonsai:
+  - source: git
+    url: URL
+    version: "^1"
+buildvariants: []
+tasks:
+  - name: greet
+    commands:
+      - bonsai: greet:hello
+"#;
+
+/// Read the generated configuration without the time it was generated at.
+fn read_config() -> String {
+    read_to_string("evergreen.yml")
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with("# Generated at"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn build(cache_dir: &Path, offline: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let git_config = GitConfig {
+        cache_dir: Some(cache_dir.to_path_buf()),
+        offline,
+        ..Default::default()
+    };
+    build_landscape(
+        Path::new("landscape.yml"),
+        Path::new("."),
+        "evergreen.yml",
+        false,
+        git_config,
+        None,
+        "src",
+    )
+}
+
+#[test]
+fn test_build_from_file_url_with_lock_file_and_offline() {
+    let test_dir = TestDir::new("build");
+    let repo_dir = test_dir.path("pots");
+    let cache_dir = test_dir.path("cache");
+
+    let repo = Repository::init(&repo_dir).unwrap();
+    let first_commit = commit_files(
+        &repo,
+        &[
+            ("bonsai.manifest.yml", MANIFEST),
+            ("bonsai/greet.bonsai.yml", POT),
+            ("bonsai/hello.sh", "echo hello\n"),
+        ],
+        "v1.0.0",
+    );
+    let url = format!("file://{}", repo_dir.display());
+    write("landscape.yml", LANDSCAPE.replace("URL", &url)).unwrap();
+
+    // The first build clones the source, copies its support files and locks its commit.
+    build(&cache_dir, false).unwrap();
+    let config = read_config();
+    assert!(config.contains("bash src/bonsai_files/bonsai/hello.sh"));
+    assert_eq!(
+        read_to_string("bonsai_files/bonsai/hello.sh").unwrap(),
+        "echo hello\n"
+    );
+    let lock = read_to_string("bonsai.lock").unwrap();
+    assert!(lock.contains(&first_commit));
+
+    // A newer matching version is not used while the lock file pins the source.
+    let second_commit = commit_files(&repo, &[("bonsai/hello.sh", "echo hi\n")], "v1.1.0");
+    build(&cache_dir, false).unwrap();
+    assert_eq!(read_to_string("bonsai.lock").unwrap(), lock);
+    assert!(!lock.contains(&second_commit));
+    assert_eq!(
+        read_to_string("bonsai_files/bonsai/hello.sh").unwrap(),
+        "echo hello\n"
+    );
+
+    // Offline builds only use the cache, so they work without the source repository.
+    remove_dir_all(&repo_dir).unwrap();
+    build(&cache_dir, true).unwrap();
+    assert_eq!(read_config(), config);
+    assert!(build(&test_dir.path("empty-cache"), true).is_err());
+}
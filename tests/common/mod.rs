@@ -0,0 +1,85 @@
+use git2::{Repository, Signature};
+use std::env::{current_dir, set_current_dir, temp_dir};
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/// Held while a test runs in its own working directory, since that is shared by all tests.
+static WORKING_DIR_LOCK: Mutex<()> = Mutex::new(());
+
+/// Temporary directory a test works in, the test runs with `project` as its working directory.
+///
+/// The previous working directory is restored and the directory removed when the test ends,
+/// even if it fails.
+pub struct TestDir {
+    root: PathBuf,
+    previous_dir: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let lock = WORKING_DIR_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let root = temp_dir().join(format!("evg-bonsai-{}-{}", name, std::process::id()));
+        if root.exists() {
+            remove_dir_all(&root).unwrap();
+        }
+        let project_dir = root.join("project");
+        create_dir_all(&project_dir).unwrap();
+        let previous_dir = current_dir().unwrap();
+        set_current_dir(&project_dir).unwrap();
+
+        Self {
+            root,
+            previous_dir,
+            _lock: lock,
+        }
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = set_current_dir(&self.previous_dir);
+        let _ = remove_dir_all(&self.root);
+    }
+}
+
+/// Commit the given files to the repository and tag the commit, returning the commit.
+pub fn commit_files(repo: &Repository, files: &[(&str, &str)], tag: &str) -> String {
+    let workdir = repo.workdir().unwrap().to_path_buf();
+    let mut index = repo.index().unwrap();
+    for (path, contents) in files {
+        let file = workdir.join(path);
+        create_dir_all(file.parent().unwrap()).unwrap();
+        write(&file, contents).unwrap();
+        index.add_path(Path::new(path)).unwrap();
+    }
+    index.write().unwrap();
+
+    let signature = Signature::now("test", "test@example.com").unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let parents = match repo.head() {
+        Ok(head) => vec![head.peel_to_commit().unwrap()],
+        Err(_) => vec![],
+    };
+    let parents: Vec<_> = parents.iter().collect();
+    let commit = repo
+        .commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "commit",
+            &tree,
+            &parents,
+        )
+        .unwrap();
+    let object = repo.find_object(commit, None).unwrap();
+    repo.tag_lightweight(tag, &object, false).unwrap();
+    commit.to_string()
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::{commit_files, TestDir};
+use evg_bonsai::landscape::landscape::BonsaiLandscape;
+use evg_bonsai::pot::credentials::GitConfig;
+use evg_bonsai::pot::loader::PotLoader;
+use evg_bonsai::pot::lockfile::BonsaiLock;
+use evg_bonsai::pot::update::find_updates;
+use git2::Repository;
+
+const MANIFEST: &str = r#"
+bonsai_pots:
+  - name: greet
+    path: greet.bonsai.yml
+    description: Greetings.
+"#;
+
+const POT_V1: &str = r#"
+name: greet
+functions:
+  hello:
+    description: Say hello.
+    actions:
+      - command: shell.exec
+        params:
+          script: echo hello
+  bye:
+    description: Say bye.
+    actions:
+      - command: shell.exec
+        params:
+          script: echo bye
+  same:
+    description: Unchanged.
+    actions:
+      - command: shell.exec
+        params:
+          script: echo same
+"#;
+
+const POT_V2: &str = r#"
+name: greet
+functions:
+  hello:
+    description: Say hello.
+    actions:
+      - command: shell.exec
+        params:
+          script: echo hi
+  wave:
+    description: Wave.
+    actions:
+      - command: shell.exec
+        params:
+          script: echo wave
+  same:
+    description: Unchanged.
+    actions:
+      - command: shell.exec
+        params:
+          script: echo same
+"#;
+
+fn resolve(landscape: &BonsaiLandscape, pot_loader: &mut PotLoader) {
+    landscape.resolve_sources(pot_loader).unwrap();
+}
+
+#[test]
+fn test_find_updates_summarizes_function_changes() {
+    let test_dir = TestDir::new("update");
+    let repo_dir = test_dir.path("pots");
+    let git_config = GitConfig {
+        cache_dir: Some(test_dir.path("cache")),
+        ..Default::default()
+    };
+
+    let repo = Repository::init(&repo_dir).unwrap();
+    let first_commit = commit_files(
+        &repo,
+        &[
+            ("bonsai.manifest.yml", MANIFEST),
+            ("greet.bonsai.yml", POT_V1),
+        ],
+        "v1.0.0",
+    );
+    let landscape: BonsaiLandscape = serde_yaml::from_str(&format!(
+        "bonsai:\n  - source: git\n    url: file://{}\nbuildvariants: []\ntasks: []\n",
+        repo_dir.display()
+    ))
+    .unwrap();
+
+    let mut pot_loader = PotLoader::new(BonsaiLock::default(), git_config.clone());
+    resolve(&landscape, &mut pot_loader);
+    let previous = pot_loader.resolved.clone();
+    assert_eq!(previous.sources[0].commit, first_commit);
+
+    let second_commit = commit_files(&repo, &[("greet.bonsai.yml", POT_V2)], "v2.0.0");
+
+    // Without updating, the locked commit is kept and nothing changes.
+    let mut pot_loader = PotLoader::new(previous.clone(), git_config.clone());
+    resolve(&landscape, &mut pot_loader);
+    assert!(find_updates(&previous, &pot_loader).unwrap().is_empty());
+
+    let mut pot_loader = PotLoader::new(previous.clone(), git_config).with_updates(vec![]);
+    resolve(&landscape, &mut pot_loader);
+    let updates = find_updates(&previous, &pot_loader).unwrap();
+
+    assert_eq!(updates.len(), 1);
+    let update = &updates[0];
+    assert_eq!(
+        update.previous_commit.as_deref(),
+        Some(first_commit.as_str())
+    );
+    assert_eq!(update.current.commit, second_commit);
+    let changes: Vec<String> = update.changes.iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+        changes,
+        vec!["- greet:bye", "~ greet:hello", "+ greet:wave"]
+    );
+    assert!(update.to_string().contains(&format!(
+        ": {} -> {}",
+        &first_commit[..7],
+        &second_commit[..7]
+    )));
+}